@@ -11,7 +11,7 @@ const PROGRAM: &'static str = "
 ";
 
 fn main() {
-    let snoot::Result { roots, diagnostics } = simple_parse(PROGRAM, &[], Some("filename.lisp"));
+    let snoot::Result { roots, diagnostics, .. } = simple_parse(PROGRAM, &[], Some("filename.lisp"));
     assert!(diagnostics.is_empty());
 
     // Report an error over the entire program
@@ -0,0 +1,538 @@
+use std::rc::Rc;
+
+use tendril::StrTendril;
+use parse::{SourceMap, Span, StartEnd};
+use token::{self, ListType, TokError, TokenInfo, TokenType, TokenizeOptions};
+use {Sexpr, SexprFold};
+
+/// A lossless concrete syntax tree.
+///
+/// Unlike `Sexpr`, every byte of the source belongs to some node: whitespace,
+/// comments, and list delimiters are kept as tokens, so writing out the text
+/// of every token in order reproduces the input exactly.  This is meant as a
+/// base for formatters and refactoring tools.
+pub struct Cst {
+    pub nodes: Vec<CstNode>,
+    pub source: StrTendril,
+}
+
+/// A node in a `Cst`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CstNode {
+    /// A list.  Its first child is the opening delimiter, and its last child
+    /// is the closing delimiter if the list was closed.
+    List {
+        list_type: ListType,
+        children: Vec<CstNode>,
+        span: Span,
+    },
+
+    /// A single token, including whitespace, comments and delimiters.
+    Token(TokenInfo, Span),
+}
+
+/// Parses some text into a `Cst` with the default `TokenizeOptions`.
+///
+/// Parsing a CST never fails: an unclosed string is kept as a string token,
+/// an unclosed list simply ends at the end of the input, and an extra closing
+/// delimiter is kept as a token where it appears.  A closing delimiter closes
+/// the innermost open list, even if the two don't match.
+pub fn parse<S: Into<StrTendril>>(string: S, splitters: &[&str], file: Option<&str>) -> Cst {
+    parse_with_options(string, splitters, file, TokenizeOptions::default())
+}
+
+/// Parses some text into a `Cst` like `parse`, with non-default `TokenizeOptions`.
+pub fn parse_with_options<S: Into<StrTendril>>(string: S,
+                                               splitters: &[&str],
+                                               file: Option<&str>,
+                                               options: TokenizeOptions)
+                                               -> Cst {
+    let string = string.into();
+    let file = file.map(|f| Rc::new(f.to_string()));
+    // The innermost open list is last; the bottom of the stack is the top level
+    let mut stack: Vec<(Option<ListType>, Vec<CstNode>)> = vec![(None, vec![])];
+
+    for token in token::tokenize_with_options(string.clone(), splitters, options) {
+        let token = match token {
+            Ok(token) => token,
+            Err(TokError::UnclosedString(span)) => {
+                TokenInfo {
+                    line_number: span.lines_covered.start as usize,
+                    column_number: span.columns.start as usize,
+                    byte_offset: span.text_bytes.start as usize,
+                    length: span.text_bytes.end - span.text_bytes.start,
+                    typ: TokenType::String,
+                }
+            }
+            // A truncated atom was already emitted, and reading a string can't fail
+            Err(TokError::AtomTooLong { .. }) |
+            Err(TokError::Io(_)) => continue,
+        };
+        let node = CstNode::Token(token, Span::from_token(&token, &string, &file));
+
+        match token.typ {
+            TokenType::ListOpening(list_type) => stack.push((Some(list_type), vec![node])),
+            TokenType::ListClosing(_) if stack.len() > 1 => {
+                let (list_type, mut children) = stack.pop().unwrap();
+                children.push(node);
+                stack.last_mut().unwrap().1.push(list_node(list_type.unwrap(), children));
+            }
+            _ => stack.last_mut().unwrap().1.push(node),
+        }
+    }
+
+    while stack.len() > 1 {
+        let (list_type, children) = stack.pop().unwrap();
+        stack.last_mut().unwrap().1.push(list_node(list_type.unwrap(), children));
+    }
+
+    Cst {
+        nodes: stack.pop().unwrap().1,
+        source: string,
+    }
+}
+
+fn list_node(list_type: ListType, children: Vec<CstNode>) -> CstNode {
+    let span = Span::from_spans(children[0].span(), children[children.len() - 1].span());
+    CstNode::List {
+        list_type: list_type,
+        children: children,
+        span: span,
+    }
+}
+
+impl Cst {
+    /// Returns every token in the tree, in source order.
+    pub fn tokens(&self) -> Vec<&CstNode> {
+        let mut out = vec![];
+        for node in &self.nodes {
+            node.push_tokens(&mut out);
+        }
+        out
+    }
+
+    /// Converts the tree into the lossy `Sexpr` form, dropping trivia.
+    pub fn to_ast(&self) -> Vec<Sexpr> {
+        nodes_to_ast(&self.nodes)
+    }
+}
+
+impl CstNode {
+    /// Returns the span over the source code that this node covers.
+    pub fn span(&self) -> &Span {
+        match self {
+            &CstNode::List { ref span, .. } => span,
+            &CstNode::Token(_, ref span) => span,
+        }
+    }
+
+    /// Returns the text that built this node.
+    pub fn text(&self) -> StrTendril {
+        self.span().text()
+    }
+
+    /// Returns true if this node is whitespace or a comment.
+    pub fn is_trivia(&self) -> bool {
+        match self {
+            &CstNode::Token(TokenInfo { typ: TokenType::Whitespace, .. }, _) |
+            &CstNode::Token(TokenInfo { typ: TokenType::Comment, .. }, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Converts this node into an `Sexpr`.
+    ///
+    /// Returns `None` for nodes that have no `Sexpr` form on their own:
+    /// trivia, delimiters and unary operators.
+    pub fn to_ast(&self) -> Option<Sexpr> {
+        match self {
+            &CstNode::List { list_type, ref children, ref span } => {
+                let opening_token = children[0].first_token();
+                let closing_token = match children.last() {
+                    Some(&CstNode::Token(token, _)) => token,
+                    Some(last) => last.last_token(),
+                    None => opening_token,
+                };
+                let inner = match children.last() {
+                    Some(&CstNode::Token(TokenInfo { typ: TokenType::ListClosing(_), .. }, _)) => {
+                        &children[1..children.len() - 1]
+                    }
+                    _ => &children[1..],
+                };
+                Some(Sexpr::List {
+                         list_type: list_type,
+                         opening_token: opening_token,
+                         closing_token: closing_token,
+                         children: nodes_to_ast(inner),
+                         span: span.clone(),
+                     })
+            }
+            &CstNode::Token(token @ TokenInfo { typ: TokenType::Atom, .. }, ref span) => {
+                Some(Sexpr::Terminal(token, span.clone()))
+            }
+            &CstNode::Token(token @ TokenInfo { typ: TokenType::String, .. }, ref span) => {
+                Some(Sexpr::String(token, span.clone()))
+            }
+            &CstNode::Token(..) => None,
+        }
+    }
+
+    fn first_token(&self) -> TokenInfo {
+        match self {
+            &CstNode::List { ref children, .. } => children[0].first_token(),
+            &CstNode::Token(token, _) => token,
+        }
+    }
+
+    fn last_token(&self) -> TokenInfo {
+        match self {
+            &CstNode::List { ref children, .. } => children[children.len() - 1].last_token(),
+            &CstNode::Token(token, _) => token,
+        }
+    }
+
+    fn push_tokens<'a>(&'a self, out: &mut Vec<&'a CstNode>) {
+        match self {
+            &CstNode::List { ref children, .. } => {
+                for child in children {
+                    child.push_tokens(out);
+                }
+            }
+            &CstNode::Token(..) => out.push(self),
+        }
+    }
+}
+
+// Converts a run of sibling nodes, applying unary operators to the
+// expression that follows them.
+fn nodes_to_ast(nodes: &[CstNode]) -> Vec<Sexpr> {
+    let mut out = vec![];
+    let mut operators = vec![];
+    for node in nodes {
+        if let &CstNode::Token(token @ TokenInfo { typ: TokenType::UnaryOperator, .. }, ref span) = node {
+            operators.push((token, span));
+            continue;
+        }
+        let mut sexpr = match node.to_ast() {
+            Some(sexpr) => sexpr,
+            None => continue,
+        };
+        while let Some((op, op_span)) = operators.pop() {
+            let span = Span::from_spans(op_span, sexpr.span());
+            sexpr = Sexpr::UnaryOperator {
+                op: op,
+                child: Box::new(sexpr),
+                span: span,
+            };
+        }
+        out.push(sexpr);
+    }
+    out
+}
+
+/// Copies the comments of `original` into `formatted`, which should hold the
+/// same expressions laid out differently, e.g. the output of a formatter.
+///
+/// A `Sexpr` tree has no comments, so printing one drops them.  Each comment
+/// in `original` is attached to the expression it follows on the same line,
+/// or else to the expression after it, and is inserted next to the matching
+/// expression in `formatted`.  Expressions are matched in order with
+/// `Sexpr::structural_eq`; a comment whose expression has no match is
+/// appended to the end.
+///
+/// `formatted`'s source and the spans of its roots are rewritten to point
+/// into the new text; its diagnostics are left as they were.  Comments are
+/// found with the default `;` comment start.
+pub fn reattach_comments(original: &::Result, formatted: &mut ::Result) {
+    let source = match original.source {
+        Some(ref source) => source,
+        None => return,
+    };
+    let nodes: Vec<&Sexpr> = original.roots.iter().flat_map(|r| r.iter_preorder()).collect();
+    let comments = find_comments(source, &nodes);
+    if comments.is_empty() {
+        return;
+    }
+
+    let text = formatted.source.clone().unwrap_or_default();
+    let formatted_nodes: Vec<&Sexpr> =
+        formatted.roots.iter().flat_map(|r| r.iter_preorder()).collect();
+    // The node in `formatted` matching each node in `original`, if any
+    let mut matches = vec![None; nodes.len()];
+    let mut next = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(found) = formatted_nodes[next..].iter().position(|f| f.structural_eq(node)) {
+            matches[i] = Some(formatted_nodes[next + found]);
+            next += found + 1;
+        }
+    }
+
+    let map = SourceMap::new(source);
+    let mut inserts: Vec<(u32, String)> = vec![];
+    for &(start, end, ref comment) in &comments {
+        let (line, _) = map.line_col(start);
+        // The outermost node that ends last before the comment, and the
+        // outermost node that starts first after it
+        let ends = nodes.iter().map(|n| n.span().text_bytes.end);
+        let starts = nodes.iter().map(|n| n.span().text_bytes.start);
+        let before = ends.clone()
+            .filter(|&e| e <= start)
+            .max()
+            .and_then(|max| ends.clone().position(|e| e == max));
+        let after = starts.clone()
+            .filter(|&s| s >= end)
+            .min()
+            .and_then(|min| starts.clone().position(|s| s == min));
+
+        let same_line = before.into_iter().any(|b| nodes[b].span().lines_covered.end == line);
+        let insert = match (before, after) {
+            (Some(b), _) if same_line => matches[b].map(|n| trailing(&text, n, comment, true)),
+            (_, Some(a)) => matches[a].map(|n| leading(n, comment)),
+            (Some(b), None) => matches[b].map(|n| trailing(&text, n, comment, false)),
+            (None, None) => None,
+        };
+        inserts.push(insert.unwrap_or_else(|| {
+            let mut insert = String::new();
+            if !text.is_empty() && !text.ends_with('\n') {
+                insert.push('\n');
+            }
+            insert.push_str(comment);
+            insert.push('\n');
+            (text.len32(), insert)
+        }));
+    }
+    // Comments inserted at the same place keep their original order
+    inserts.sort_by_key(|&(pos, _)| pos);
+
+    let inserted: usize = inserts.iter().map(|i| i.1.len()).sum();
+    let mut new_text = String::with_capacity(text.len() + inserted);
+    let mut copied = 0;
+    for &(pos, ref insert) in &inserts {
+        new_text.push_str(&text[copied..pos as usize]);
+        new_text.push_str(insert);
+        copied = pos as usize;
+    }
+    new_text.push_str(&text[copied..]);
+
+    let new_text = StrTendril::from(new_text);
+    let mut remap = Remap {
+        map: SourceMap::new(&new_text),
+        text: new_text.clone(),
+        inserts: inserts.into_iter().map(|(pos, insert)| (pos, insert.len() as u32)).collect(),
+    };
+    let roots = formatted.roots.drain(..).map(|r| remap.fold(r)).collect();
+    formatted.roots = roots;
+    formatted.source = Some(new_text);
+}
+
+// Returns the start, end and text of every comment in `source` that lies
+// outside the tokens of `nodes`.
+fn find_comments(source: &StrTendril, nodes: &[&Sexpr]) -> Vec<(u32, u32, String)> {
+    let mut covered = vec![];
+    for node in nodes {
+        match **node {
+            Sexpr::List { ref opening_token, ref closing_token, .. } => {
+                covered.push(token_range(opening_token));
+                covered.push(token_range(closing_token));
+            }
+            Sexpr::UnaryOperator { ref op, .. } => covered.push(token_range(op)),
+            Sexpr::WithMeta { ref caret, .. } => covered.push(token_range(caret)),
+            Sexpr::Terminal(_, ref span) |
+            Sexpr::String(_, ref span) => {
+                covered.push((span.text_bytes.start, span.text_bytes.end))
+            }
+        }
+    }
+    covered.sort();
+    covered.push((source.len32(), source.len32()));
+
+    // Only whitespace and comments can lie between the tokens of the tree
+    let mut comments = vec![];
+    let mut pos = 0;
+    for (start, end) in covered {
+        if start > pos {
+            let gap = source.subtendril(pos, start - pos);
+            for token in token::tokenize(gap.clone(), &[]) {
+                if let Ok(token @ TokenInfo { typ: TokenType::Comment, .. }) = token {
+                    let offset = token.byte_offset as u32;
+                    let comment = gap[offset as usize..(offset + token.length) as usize].trim();
+                    comments.push((pos + offset, pos + offset + token.length, comment.to_string()));
+                }
+            }
+        }
+        pos = ::std::cmp::max(pos, end);
+    }
+    comments
+}
+
+fn token_range(token: &TokenInfo) -> (u32, u32) {
+    (token.byte_offset as u32, token.byte_offset as u32 + token.length)
+}
+
+// Puts `comment` after `node`, on the same line or on a line of its own,
+// and lines up whatever followed `node` with it.
+fn trailing(text: &StrTendril, node: &Sexpr, comment: &str, same_line: bool) -> (u32, String) {
+    let span = node.span();
+    let indent = span.columns.start as usize - 1;
+    let mut insert = if same_line {
+        format!(" {}", comment)
+    } else {
+        format!("\n{}{}", " ".repeat(indent), comment)
+    };
+    let rest = &text[span.text_bytes.end as usize..];
+    let spaces = rest.bytes().take_while(|&b| b == b' ' || b == b'\t').count();
+    if !rest[spaces..].is_empty() && !rest[spaces..].starts_with('\n') {
+        insert.push('\n');
+        insert.push_str(&" ".repeat(indent.saturating_sub(spaces)));
+    }
+    (span.text_bytes.end, insert)
+}
+
+// Puts `comment` on its own line before `node`.
+fn leading(node: &Sexpr, comment: &str) -> (u32, String) {
+    let span = node.span();
+    let indent = " ".repeat(span.columns.start as usize - 1);
+    (span.text_bytes.start, format!("{}\n{}", comment, indent))
+}
+
+// Moves the spans of a tree into the text made by inserting `inserts`.
+struct Remap {
+    text: StrTendril,
+    map: SourceMap,
+    // The position and length of each insertion, in the old text
+    inserts: Vec<(u32, u32)>,
+}
+
+impl Remap {
+    // Text inserted where a token starts goes before it, and text inserted
+    // where one ends goes after it.
+    fn start(&self, offset: u32) -> u32 {
+        offset + self.inserts.iter().filter(|i| i.0 <= offset).map(|i| i.1).sum::<u32>()
+    }
+
+    fn end(&self, offset: u32) -> u32 {
+        offset + self.inserts.iter().filter(|i| i.0 < offset).map(|i| i.1).sum::<u32>()
+    }
+
+    fn token(&self, token: TokenInfo) -> TokenInfo {
+        let start = self.start(token.byte_offset as u32);
+        let (line, column) = self.map.line_col(start);
+        TokenInfo {
+            line_number: line as usize,
+            column_number: column as usize,
+            byte_offset: start as usize,
+            ..token
+        }
+    }
+
+    fn span(&self, span: Span) -> Span {
+        let start = self.start(span.text_bytes.start);
+        let end = ::std::cmp::max(start, self.end(span.text_bytes.end));
+        let (start_line, start_column) = self.map.line_col(start);
+        let (end_line, end_column) = self.map.line_col(end);
+        Span {
+            text_bytes: StartEnd { start: start, end: end },
+            lines_covered: StartEnd { start: start_line, end: end_line },
+            columns: StartEnd { start: start_column, end: end_column },
+            full_text: self.text.clone(),
+            file: span.file,
+        }
+    }
+}
+
+impl SexprFold for Remap {
+    fn fold_list(&mut self,
+                 list_type: ListType,
+                 opening_token: TokenInfo,
+                 closing_token: TokenInfo,
+                 children: Vec<Sexpr>,
+                 span: Span)
+                 -> Sexpr {
+        Sexpr::List {
+            list_type: list_type,
+            opening_token: self.token(opening_token),
+            closing_token: self.token(closing_token),
+            children: children,
+            span: self.span(span),
+        }
+    }
+
+    fn fold_unary_operator(&mut self, op: TokenInfo, child: Sexpr, span: Span) -> Sexpr {
+        Sexpr::UnaryOperator {
+            op: self.token(op),
+            child: Box::new(child),
+            span: self.span(span),
+        }
+    }
+
+    fn fold_with_meta(&mut self, caret: TokenInfo, meta: Sexpr, expr: Sexpr, span: Span) -> Sexpr {
+        Sexpr::WithMeta {
+            caret: self.token(caret),
+            meta: Box::new(meta),
+            expr: Box::new(expr),
+            span: self.span(span),
+        }
+    }
+
+    fn fold_terminal(&mut self, token: TokenInfo, span: Span) -> Sexpr {
+        Sexpr::Terminal(self.token(token), self.span(span))
+    }
+
+    fn fold_string(&mut self, token: TokenInfo, span: Span) -> Sexpr {
+        Sexpr::String(self.token(token), self.span(span))
+    }
+}
+
+#[test]
+fn lossless_round_trip() {
+    let inputs = ["(define (f x)\n  ; doubles x\n  (* x 2))  ",
+                  "[a {b c}] \"str\\\"ing\"\t\n",
+                  "(unclosed (list",
+                  "extra ) closer",
+                  "(a \"unclosed string"];
+    for input in &inputs {
+        let cst = parse(*input, &[], None);
+        let text: String = cst.tokens().iter().map(|t| t.text().to_string()).collect();
+        assert_eq!(&text, input);
+    }
+}
+
+#[test]
+fn to_ast_matches_parser() {
+    let input = "(define (f x)\n  ; doubles x\n  (* x 2)) [a {b \"c\"}]";
+    let cst = parse(input, &[], Some("file"));
+    let ::Result { roots, diagnostics, .. } = ::simple_parse(input, &[], Some("file"));
+    assert!(diagnostics.is_empty());
+    assert_eq!(cst.to_ast(), roots);
+
+    let options = TokenizeOptions::new().with_unary_operators(&["'"]);
+    let cst = parse_with_options("('a '(b))", &[], None, options);
+    assert_eq!(cst.to_ast()[0].to_source_string(), "('a '(b))");
+    assert_eq!(cst.to_ast()[0].count_matching(|s| s.kind() == ::parse::SexprKind::UnaryOperator), 2);
+}
+
+#[test]
+fn reattach_comments_after_reformat() {
+    let original = ::simple_parse("(a ; note\n b)", &[], None);
+    let mut formatted = ::simple_parse("(a b)", &[], None);
+    reattach_comments(&original, &mut formatted);
+    assert_eq!(&*formatted.source().unwrap(), "(a ; note\n b)");
+    assert!(formatted.roots[0].structural_eq(&original.roots[0]));
+    assert_eq!(formatted.roots[0].span().text().as_ref(), "(a ; note\n b)");
+    let b = formatted.roots[0].iter_preorder().nth(2).unwrap().span();
+    assert_eq!(b.text().as_ref(), "b");
+    assert_eq!((b.lines_covered.start, b.columns.start), (2, 2));
+
+    let original = ::simple_parse("; header\n(def x ; the x\n  1)\n; footer", &[], None);
+    let mut formatted = ::simple_parse("(def x 1)", &[], None);
+    reattach_comments(&original, &mut formatted);
+    assert_eq!(&*formatted.source().unwrap(), "; header\n(def x ; the x\n     1)\n; footer");
+    let map = formatted.source_map();
+    for sexpr in formatted.roots[0].iter_preorder() {
+        let span = sexpr.span();
+        assert_eq!(map.line_col(span.text_bytes.start),
+                   (span.lines_covered.start, span.columns.start));
+        assert_eq!(map.line_col(span.text_bytes.end),
+                   (span.lines_covered.end, span.columns.end));
+    }
+}
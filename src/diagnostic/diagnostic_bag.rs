@@ -1,7 +1,10 @@
 use super::*;
 use std::iter::FromIterator;
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::fmt::{Display, Formatter, Debug};
 use std::fmt::Result as FmtResult;
+use std::cmp::Ordering;
 
 /// A structure that contains Snoot errors for easy sorting and printing
 #[derive(Eq, PartialEq)]
@@ -21,24 +24,66 @@ impl DiagnosticBag {
         DiagnosticBag { diagnostics: vec![] }
     }
 
+    /// Constructs an empty DiagnosticBag with space for at least
+    /// `capacity` diagnostics before reallocating.
+    pub fn with_capacity(capacity: usize) -> DiagnosticBag {
+        DiagnosticBag { diagnostics: Vec::with_capacity(capacity) }
+    }
+
+    /// Returns the number of diagnostics the bag can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.diagnostics.capacity()
+    }
+
     /// Costructs a diagnostic bag from a Vec
     pub fn from_vec(v: Vec<Diagnostic>) -> DiagnosticBag {
         DiagnosticBag { diagnostics: v }
     }
 
+    /// Consumes the bag, returning the diagnostics it contained
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    /// Returns the diagnostics in the bag as a slice
+    pub fn as_slice(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     pub fn len(&self) -> usize {
         self.diagnostics.len()
     }
 
     /// Sorts the errors contained in the bag for better printing.
     ///
-    /// The sort order is by filename (primary) and by file location (secondary)
+    /// The order is by file name, then by the byte offset that the
+    /// diagnostic starts at, then by error level (see `DiagnosticLevel`).
+    /// Any remaining ties are broken by comparing the rest of the diagnostic,
+    /// message included, so this is a total order: only diagnostics that are
+    /// exactly equal compare as equal.
+    ///
+    /// The sort is stable.
     pub fn sort(&mut self) {
-        use std::cmp::Ord;
-        self.diagnostics
-            .sort_by(|e1, e2| e1.global_span.cmp(&e2.global_span));
-        self.diagnostics
-            .sort_by(|e1, e2| e1.global_span.file.cmp(&e2.global_span.file));
+        self.diagnostics.sort_by(compare_for_sort);
+    }
+
+    /// Sorts the bag into the same order as `sort`, but may be faster on
+    /// large bags.
+    ///
+    /// The sort isn't stable, but since the order is total, only diagnostics
+    /// that are exactly equal can end up swapped.
+    pub fn sort_unstable(&mut self) {
+        self.diagnostics.sort_unstable_by(compare_for_sort);
+    }
+
+    /// Sorts the bag and removes diagnostics that are exact duplicates
+    /// of one another, keeping a single copy of each.
+    ///
+    /// The bag is left sorted in the same order as `sort`.
+    pub fn dedup(&mut self) {
+        // The order is total, so duplicates end up adjacent
+        self.sort_unstable();
+        self.diagnostics.dedup();
     }
 
     /// Appends another ErrorBag onto this one.
@@ -71,6 +116,27 @@ impl DiagnosticBag {
         false
     }
 
+    /// Returns a new bag holding copies of the diagnostics with exactly the
+    /// error level `level`.
+    pub fn filter_level(&self, level: DiagnosticLevel) -> DiagnosticBag {
+        DiagnosticBag {
+            diagnostics: self.diagnostics
+                .iter()
+                .filter(|d| d.error_level == level)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Drops every diagnostic whose error level sorts below `min`.
+    ///
+    /// Levels are ordered `Info < Warn < Error < Custom`, so
+    /// `retain_level(DiagnosticLevel::Error)` keeps errors and custom
+    /// diagnostics.
+    pub fn retain_level(&mut self, min: DiagnosticLevel) {
+        self.diagnostics.retain(|d| d.error_level >= min);
+    }
+
     /// Returns true if the bag contains any error with error level "Info"
     pub fn contains_info(&self) -> bool {
         for error in &self.diagnostics {
@@ -109,6 +175,13 @@ impl DiagnosticBag {
         self.diagnostics.is_empty()
     }
 
+    /// Returns true if there are no diagnostics with error level "Error" or
+    /// "Warn".  Unlike `is_empty`, the bag may still contain info and custom
+    /// diagnostics.
+    pub fn is_clean(&self) -> bool {
+        !self.contains_errors() && !self.contains_warnings()
+    }
+
     /// If the bag isn't empty, this will panic with the diagnostic
     /// messages as the panic string.
     pub fn assert_empty(&self) {
@@ -142,31 +215,22 @@ impl DiagnosticBag {
         source: 'ex'
     */
     pub fn to_json(&self) -> ::serde_json::Value {
+        self.to_json_with_options(false)
+    }
+
+    /// Like `to_json`, but when `lsp_utf16` is set the `character` positions
+    /// count UTF-16 code units instead of bytes, which is what editors that
+    /// speak the Language Server Protocol expect.
+    pub fn to_json_with_options(&self, lsp_utf16: bool) -> ::serde_json::Value {
         use serde_json::Value;
 
         let mut all = vec![];
         for diagnostic in &self.diagnostics {
-            let sev = match diagnostic.error_level {
-                DiagnosticLevel::Error => 0,
-                DiagnosticLevel::Warn => 1,
-                DiagnosticLevel::Info => 2,
-                DiagnosticLevel::Custom(_) => 3,
-            };
-
             let map = json!({
-                "severity": sev,
+                "severity": json_severity(&diagnostic.error_level),
                 "message": diagnostic.message,
                 "source": "implicit lint",
-                "range": {
-                    "start": {
-                        "line": diagnostic.global_span.lines_covered.start - 1,
-                        "character": diagnostic.global_span.columns.start - 1,
-                    },
-                    "end": {
-                        "line": diagnostic.global_span.lines_covered.end - 1,
-                        "character": diagnostic.global_span.columns.end - 1,
-                    },
-                }
+                "range": json_range(&diagnostic.global_span, lsp_utf16),
             });
             all.push(map);
         }
@@ -174,9 +238,290 @@ impl DiagnosticBag {
         Value::Array(all)
     }
 
+    /// Formats the diagnostics as Language Server Protocol diagnostics.
+    ///
+    /// This is like `to_json`, but every diagnostic's `source` is
+    /// `source_name`, diagnostics with a custom error level carry it as their
+    /// `code`, and each annotation becomes a `relatedInformation` entry with
+    /// its own range.  An annotation's `uri` is the file name of its span, or
+    /// empty if it has none.
+    pub fn to_lsp_json(&self, source_name: &str) -> ::serde_json::Value {
+        use serde_json::Value;
+
+        let mut all = vec![];
+        for diagnostic in &self.diagnostics {
+            let related: Vec<Value> = diagnostic.annotations
+                .iter()
+                .map(|annotation| {
+                    json!({
+                        "location": {
+                            "uri": annotation.span.file_name().unwrap_or(""),
+                            "range": json_range(&annotation.span, false),
+                        },
+                        "message": annotation.message,
+                    })
+                })
+                .collect();
+
+            let mut map = json!({
+                "severity": json_severity(&diagnostic.error_level),
+                "message": diagnostic.message,
+                "source": source_name,
+                "range": json_range(&diagnostic.global_span, false),
+                "relatedInformation": related,
+            });
+            if let DiagnosticLevel::Custom(ref code) = diagnostic.error_level {
+                map["code"] = Value::String(code.clone());
+            }
+            all.push(map);
+        }
+
+        Value::Array(all)
+    }
+
+    /// Formats the diagnostics as a minimal SARIF 2.1.0 log, as consumed by
+    /// code scanning tools.
+    ///
+    /// The log has one run, with one result per diagnostic.  Errors have
+    /// level `error`, warnings `warning`, and everything else `note`.
+    /// Columns are counted in UTF-16 code units, which is the SARIF default,
+    /// except for spans without their source text, which keep their byte
+    /// columns.  A diagnostic whose span has no file has no location.
+    pub fn to_sarif(&self) -> ::serde_json::Value {
+        use serde_json::Value;
+
+        let mut results = vec![];
+        for diagnostic in &self.diagnostics {
+            let level = match diagnostic.error_level {
+                DiagnosticLevel::Error => "error",
+                DiagnosticLevel::Warn => "warning",
+                DiagnosticLevel::Info |
+                DiagnosticLevel::Custom(_) => "note",
+            };
+
+            let span = &diagnostic.global_span;
+            let locations: Vec<Value> = span.file_name()
+                .into_iter()
+                .map(|file| {
+                    let columns = span.utf16_columns();
+                    json!({
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": file },
+                            "region": {
+                                "startLine": span.lines_covered.start,
+                                "startColumn": columns.start,
+                                "endLine": span.lines_covered.end,
+                                "endColumn": columns.end,
+                            },
+                        },
+                    })
+                })
+                .collect();
+
+            results.push(json!({
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": locations,
+            }));
+        }
+
+        json!({
+            "version": "2.1.0",
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "runs": [{
+                "tool": { "driver": { "name": "snoot" } },
+                "results": results,
+            }],
+        })
+    }
+
+    /// Formats the diagnostics as GitHub Actions workflow commands, one per line.
+    ///
+    /// Errors become `::error`, warnings become `::warning`, and everything
+    /// else becomes `::notice`.
+    pub fn to_github_actions(&self) -> String {
+        let mut out = String::new();
+        for diagnostic in &self.diagnostics {
+            let command = match diagnostic.error_level {
+                DiagnosticLevel::Error => "error",
+                DiagnosticLevel::Warn => "warning",
+                DiagnosticLevel::Info |
+                DiagnosticLevel::Custom(_) => "notice",
+            };
+
+            let span = &diagnostic.global_span;
+            let mut properties = vec![];
+            if let Some(ref file) = span.file {
+                properties.push(format!("file={}", escape_github_property(file)));
+            }
+            properties.push(format!("line={}", span.lines_covered.start));
+            properties.push(format!("col={}", span.columns.start));
+            properties.push(format!("endLine={}", span.lines_covered.end));
+            properties.push(format!("endColumn={}", span.columns.end));
+
+            out.push_str(&format!("::{} {}::{}\n",
+                                  command,
+                                  properties.join(","),
+                                  escape_github_data(&diagnostic.message)));
+        }
+        out
+    }
+
     pub fn iter(&self) -> ::std::slice::Iter<Diagnostic> {
         self.diagnostics.iter()
     }
+
+    /// Writes every diagnostic like `Display` does, optionally with ANSI
+    /// colors: the level prefix is bold and colored by severity (red for
+    /// errors, yellow for warnings, blue for info), and the gutter is dimmed.
+    ///
+    /// `ColorChoice::Auto` decides by whether stderr is a terminal, not `w`,
+    /// and only with the `auto-color` feature.
+    pub fn write_colored<W: Write>(&self, w: &mut W, color: ColorChoice) -> io::Result<()> {
+        if !color.should_color() {
+            return write!(w, "{}", self);
+        }
+        for diagnostic in &self.diagnostics {
+            writeln!(w, "{}", Colored(diagnostic))?;
+        }
+        Ok(())
+    }
+
+    /// Clusters diagnostics from the same source whose spans are at most
+    /// `gap` lines apart, directly or through other diagnostics in the
+    /// cluster.  Clusters are ordered by their first line, and so are the
+    /// diagnostics within them.
+    pub fn group_by_proximity(&self, gap: usize) -> Vec<Vec<&Diagnostic>> {
+        let mut sorted: Vec<&Diagnostic> = self.diagnostics.iter().collect();
+        sorted.sort_by_key(|d| (d.global_span.file.clone(), d.global_span.lines_covered.start));
+
+        let mut groups: Vec<(Span, Vec<&Diagnostic>)> = vec![];
+        for diagnostic in sorted {
+            let span = &diagnostic.global_span;
+            if let Some(&mut (ref mut covering, ref mut group)) = groups.last_mut() {
+                let same_source = covering.file == span.file &&
+                                  covering.full_text.as_ptr() == span.full_text.as_ptr() &&
+                                  covering.full_text.len() == span.full_text.len();
+                if same_source && span_distance(covering, span) <= gap {
+                    *covering = Span::from_spans(covering, span);
+                    group.push(diagnostic);
+                    continue;
+                }
+            }
+            groups.push((span.clone(), vec![diagnostic]));
+        }
+        groups.into_iter().map(|(_, group)| group).collect()
+    }
+
+    /// Writes the diagnostics in the clusters made by `group_by_proximity`.
+    ///
+    /// A cluster of one is written like `Display` writes it.  A larger
+    /// cluster lists each diagnostic's message and location, then shows the
+    /// source lines they cover once, with every diagnostic's span underlined
+    /// and labeled with its message.
+    pub fn write_grouped<W: Write>(&self, w: &mut W, gap: usize) -> io::Result<()> {
+        for group in self.group_by_proximity(gap) {
+            writeln!(w, "{}", Grouped(&group))?;
+        }
+        Ok(())
+    }
+
+    /// Formats the bag like `Display`, but renders snippets for spans that
+    /// carry no source text by looking up their file in `sources`.
+    ///
+    /// This is useful for diagnostics that were built outside of a parse
+    /// (for example, read back from another tool) and only know their
+    /// file, line, and column.
+    pub fn fmt_with_sources(&self, sources: &HashMap<String, String>) -> String {
+        let mut out = String::new();
+        for diagnostic in &self.diagnostics {
+            let mut diagnostic = diagnostic.clone();
+            attach_source(&mut diagnostic.global_span, sources);
+            for annotation in &mut diagnostic.annotations {
+                attach_source(&mut annotation.span, sources);
+            }
+            out.push_str(&format!("{}\n", diagnostic));
+        }
+        out
+    }
+}
+
+// Points `span` into its file's contents from `sources` if it has no source
+// text of its own, recomputing its byte range from its lines and columns.
+fn attach_source(span: &mut Span, sources: &HashMap<String, String>) {
+    if !span.full_text.is_empty() {
+        return;
+    }
+    let source = match span.file.as_ref().and_then(|f| sources.get(f.as_str())) {
+        Some(source) => source,
+        None => return,
+    };
+
+    let start = byte_offset(source, span.lines_covered.start, span.columns.start);
+    let end = byte_offset(source, span.lines_covered.end, span.columns.end);
+    span.full_text = source.as_str().into();
+    span.text_bytes.start = start;
+    span.text_bytes.end = ::std::cmp::max(start, end);
+}
+
+// Converts a 1-based line and column into a byte offset, clamped to the
+// line it is on.
+fn byte_offset(source: &str, line: u32, column: u32) -> u32 {
+    let mut line_start = 0;
+    for _ in 1..line {
+        match source[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return source.len() as u32,
+        }
+    }
+    let line_len = source[line_start..].find('\n').unwrap_or(source.len() - line_start);
+    let column = ::std::cmp::min(column.saturating_sub(1) as usize, line_len);
+    (line_start + column) as u32
+}
+
+fn escape_github_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+// The order used by `sort` and `sort_unstable`
+fn compare_for_sort(a: &Diagnostic, b: &Diagnostic) -> Ordering {
+    a.global_span.file.cmp(&b.global_span.file)
+        .then(a.global_span.text_bytes.start.cmp(&b.global_span.text_bytes.start))
+        .then(a.error_level.cmp(&b.error_level))
+        .then(a.cmp(b))
+}
+
+// The severities used by `to_json` and `to_lsp_json`
+fn json_severity(level: &DiagnosticLevel) -> u32 {
+    match *level {
+        DiagnosticLevel::Error => 0,
+        DiagnosticLevel::Warn => 1,
+        DiagnosticLevel::Info => 2,
+        DiagnosticLevel::Custom(_) => 3,
+    }
+}
+
+// Builds a zero-based `{start, end}` range of lines and characters.
+fn json_range(span: &Span, utf16: bool) -> ::serde_json::Value {
+    let columns = if utf16 {
+        span.utf16_columns()
+    } else {
+        span.columns
+    };
+    json!({
+        "start": {
+            "line": span.lines_covered.start - 1,
+            "character": columns.start - 1,
+        },
+        "end": {
+            "line": span.lines_covered.end - 1,
+            "character": columns.end - 1,
+        },
+    })
+}
+
+fn escape_github_property(s: &str) -> String {
+    escape_github_data(s).replace(':', "%3A").replace(',', "%2C")
 }
 
 impl FromIterator<Diagnostic> for DiagnosticBag {
@@ -201,3 +546,309 @@ impl Debug for DiagnosticBag {
         write!(formatter, "{}", self)
     }
 }
+
+#[test]
+fn sort_test() {
+    use std::rc::Rc;
+
+    let ::Result { roots, .. } = ::simple_parse("(a b c d)", &[], None);
+    let levels = [DiagnosticLevel::Error, DiagnosticLevel::Info, DiagnosticLevel::Warn];
+    let mut diagnostics = vec![];
+    for i in 0..300 {
+        let mut span = roots[0].iter_preorder().nth(i % 5).unwrap().span().clone();
+        span.file = if i % 7 == 0 { None } else { Some(Rc::new(format!("file{}", i % 2))) };
+        diagnostics.push(Diagnostic::new(format!("message {}", i % 4), &span)
+            .with_error_level(levels[i % 3].clone()));
+    }
+
+    // A fixed shuffle, so that the test is repeatable
+    let mut shuffled = diagnostics.clone();
+    let mut state: u64 = 12345;
+    for i in (1..shuffled.len()).rev() {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        shuffled.swap(i, (state >> 33) as usize % (i + 1));
+    }
+
+    let mut stable = DiagnosticBag::from_vec(diagnostics);
+    stable.sort();
+    let mut unstable = DiagnosticBag::from_vec(shuffled);
+    unstable.sort_unstable();
+    assert!(stable == unstable);
+
+    let sorted = stable.into_vec();
+    for pair in sorted.windows(2) {
+        let (a, b) = (&pair[0].global_span, &pair[1].global_span);
+        assert!((&a.file, a.text_bytes.start) <= (&b.file, b.text_bytes.start));
+        if (&a.file, a.text_bytes.start) == (&b.file, b.text_bytes.start) {
+            assert!(pair[0].error_level <= pair[1].error_level);
+        }
+    }
+}
+
+#[test]
+fn dedup_test() {
+    let ::Result { roots, .. } = ::simple_parse("(a b)", &[], None);
+    let a = Diagnostic::new("duplicate", roots[0].span());
+    let b = Diagnostic::new("duplicate", roots[0].span()).with_error_level(DiagnosticLevel::Warn);
+
+    let mut bag = DiagnosticBag::from_vec(vec![a.clone(), b.clone(), a.clone()]);
+    bag.dedup();
+    assert_eq!(bag.len(), 2);
+    assert_eq!(bag.iter().filter(|d| **d == a).count(), 1);
+    assert_eq!(bag.iter().filter(|d| **d == b).count(), 1);
+}
+
+#[test]
+fn with_capacity_test() {
+    let mut bag = DiagnosticBag::with_capacity(16);
+    assert!(bag.capacity() >= 16);
+    assert!(bag.is_empty());
+
+    for _ in 0..16 {
+        bag.add(Diagnostic::new("message", &Span::empty()));
+    }
+    assert_eq!(bag.len(), 16);
+    assert!(bag.capacity() >= 16);
+}
+
+#[test]
+fn github_actions_test() {
+    let ::Result { roots, .. } = ::simple_parse("(a\n  bad)", &[], Some("src/config.lisp"));
+    let bad = match &roots[0] {
+        &::Sexpr::List { ref children, .. } => children[1].span().clone(),
+        _ => unreachable!(),
+    };
+
+    let bag = DiagnosticBag::from_vec(vec![
+        Diagnostic::new("unknown symbol `bad`", &bad),
+        Diagnostic::new("50% done\nsoon", &bad).with_error_level(DiagnosticLevel::Warn),
+    ]);
+    assert_eq!(bag.to_github_actions(),
+               "::error file=src/config.lisp,line=2,col=3,endLine=2,endColumn=6::unknown symbol `bad`\n\
+                ::warning file=src/config.lisp,line=2,col=3,endLine=2,endColumn=6::50%25 done%0Asoon\n");
+}
+
+#[test]
+fn to_json_utf16_test() {
+    let ::Result { roots, .. } = ::simple_parse("(\u{1F600} bad)", &[], None);
+    let bad = roots[0].iter_preorder().find(|s| s.text().as_ref() == "bad").unwrap().span().clone();
+    let bag = DiagnosticBag::from_vec(vec![Diagnostic::new("bad", &bad)]);
+    assert_eq!(bag.to_json()[0]["range"]["start"]["character"], json!(6));
+    assert_eq!(bag.to_json_with_options(true)[0]["range"]["start"]["character"], json!(4));
+    assert_eq!(bag.to_json_with_options(true)[0]["range"]["end"]["character"], json!(7));
+}
+
+#[test]
+fn to_lsp_json_test() {
+    let ::Result { roots, .. } = ::simple_parse("(a\n  bad)", &[], Some("src/main.lisp"));
+    let bad = roots[0].iter_preorder().find(|s| s.text().as_ref() == "bad").unwrap().span().clone();
+    let bag = DiagnosticBag::from_vec(vec![
+        Diagnostic::new("unknown `bad`", &bad).add_annotation(
+            DiagnosticAnnotation::new("in this list".into(), roots[0].span().clone())),
+        Diagnostic::new("style", &bad).with_error_level(DiagnosticLevel::Custom("lint".into())),
+    ]);
+
+    assert_eq!(bag.to_lsp_json("snoot"),
+               json!([{
+                   "severity": 0,
+                   "message": "unknown `bad`",
+                   "source": "snoot",
+                   "range": {
+                       "start": { "line": 1, "character": 2 },
+                       "end": { "line": 1, "character": 5 },
+                   },
+                   "relatedInformation": [{
+                       "location": {
+                           "uri": "src/main.lisp",
+                           "range": {
+                               "start": { "line": 0, "character": 0 },
+                               "end": { "line": 1, "character": 6 },
+                           },
+                       },
+                       "message": "in this list",
+                   }],
+               }, {
+                   "severity": 3,
+                   "code": "lint",
+                   "message": "style",
+                   "source": "snoot",
+                   "range": {
+                       "start": { "line": 1, "character": 2 },
+                       "end": { "line": 1, "character": 5 },
+                   },
+                   "relatedInformation": [],
+               }]));
+}
+
+#[test]
+fn to_sarif_test() {
+    use parse::StartEnd;
+    let ::Result { roots, .. } = ::simple_parse("(a\n  bad)", &[], Some("src/main.lisp"));
+    let bad = roots[0].iter_preorder().find(|s| s.text().as_ref() == "bad").unwrap().span().clone();
+    let bag = DiagnosticBag::from_vec(vec![
+        Diagnostic::new("unknown `bad`", &bad),
+        Diagnostic::new("no file", &Span::empty()).with_error_level(DiagnosticLevel::Info),
+    ]);
+    let detached = Span {
+        text_bytes: StartEnd { start: 12, end: 15 },
+        lines_covered: StartEnd { start: 2, end: 2 },
+        columns: StartEnd { start: 3, end: 6 },
+        full_text: "".into(),
+        file: Some(::std::rc::Rc::new("src/config.lisp".into())),
+    };
+    let region = |bag: &DiagnosticBag| {
+        let sarif = bag.to_sarif();
+        sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"]["region"].clone()
+    };
+    assert_eq!(region(&DiagnosticBag::singleton(Diagnostic::new("unknown `bad`", &detached))),
+               json!({ "startLine": 2, "startColumn": 3, "endLine": 2, "endColumn": 6 }));
+
+    assert_eq!(bag.to_sarif(),
+               json!({
+                   "version": "2.1.0",
+                   "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                   "runs": [{
+                       "tool": { "driver": { "name": "snoot" } },
+                       "results": [{
+                           "level": "error",
+                           "message": { "text": "unknown `bad`" },
+                           "locations": [{
+                               "physicalLocation": {
+                                   "artifactLocation": { "uri": "src/main.lisp" },
+                                   "region": {
+                                       "startLine": 2,
+                                       "startColumn": 3,
+                                       "endLine": 2,
+                                       "endColumn": 6,
+                                   },
+                               },
+                           }],
+                       }, {
+                           "level": "note",
+                           "message": { "text": "no file" },
+                           "locations": [],
+                       }],
+                   }],
+               }));
+}
+
+#[test]
+fn is_clean_test() {
+    let with_level = |level| {
+        let diagnostic = Diagnostic::new("message", &Span::empty()).with_error_level(level);
+        DiagnosticBag::from_vec(vec![diagnostic])
+    };
+
+    assert!(DiagnosticBag::new().is_clean());
+    assert!(with_level(DiagnosticLevel::Info).is_clean());
+    assert!(!with_level(DiagnosticLevel::Info).is_empty());
+    assert!(!with_level(DiagnosticLevel::Warn).is_clean());
+    assert!(!with_level(DiagnosticLevel::Error).is_clean());
+}
+
+#[test]
+fn level_filtering_test() {
+    let with_level = |level: DiagnosticLevel| {
+        Diagnostic::new(level.as_str().to_string(), &Span::empty()).with_error_level(level)
+    };
+    let messages = |bag: &DiagnosticBag| bag.iter().map(|d| d.message.clone()).collect::<Vec<_>>();
+    let mut bag = DiagnosticBag::from_vec(vec![with_level(DiagnosticLevel::Error),
+                                               with_level(DiagnosticLevel::Info),
+                                               with_level(DiagnosticLevel::Custom("lint".into())),
+                                               with_level(DiagnosticLevel::Warn),
+                                               with_level(DiagnosticLevel::Error)]);
+
+    assert_eq!(messages(&bag.filter_level(DiagnosticLevel::Error)), vec!["error", "error"]);
+    assert!(bag.filter_level(DiagnosticLevel::Custom("other".into())).is_empty());
+    assert_eq!(bag.len(), 5);
+
+    bag.retain_level(DiagnosticLevel::Warn);
+    assert_eq!(messages(&bag), vec!["error", "lint", "warn", "error"]);
+    bag.retain_level(DiagnosticLevel::Error);
+    assert_eq!(messages(&bag), vec!["error", "lint", "error"]);
+}
+
+#[test]
+fn vec_round_trip() {
+    let diagnostics = vec![Diagnostic::new("first", &Span::empty()),
+                           Diagnostic::new("second", &Span::empty())];
+    let bag = DiagnosticBag::from_vec(diagnostics.clone());
+    assert_eq!(bag.as_slice(), &diagnostics[..]);
+    assert_eq!(bag.into_vec(), diagnostics);
+}
+
+#[test]
+fn fmt_with_sources_test() {
+    use parse::StartEnd;
+    let span = Span {
+        text_bytes: StartEnd { start: 0, end: 0 },
+        lines_covered: StartEnd { start: 2, end: 2 },
+        columns: StartEnd { start: 3, end: 6 },
+        full_text: "".into(),
+        file: Some(::std::rc::Rc::new("src/config.lisp".into())),
+    };
+    let bag = DiagnosticBag::singleton(Diagnostic::new("unknown symbol `bad`", &span));
+
+    let mut sources = HashMap::new();
+    sources.insert("src/config.lisp".to_string(), "(a\n  bad)\n".to_string());
+    assert_eq!(bag.fmt_with_sources(&sources),
+               "error: unknown symbol `bad`\n --> src/config.lisp:2:3\n2 |   bad)\n\n");
+
+    // Files missing from the map render the same as `Display`
+    assert_eq!(bag.fmt_with_sources(&HashMap::new()), bag.to_string());
+}
+
+#[test]
+fn group_by_proximity_test() {
+    let source = "(a\n b\n c)\n\n\n\n(d)";
+    let ::Result { roots, .. } = ::simple_parse(source, &[], Some("x"));
+    let find = |text: &str| {
+        roots.iter().flat_map(|r| r.iter_preorder()).find(|s| s.text().as_ref() == text).unwrap()
+    };
+    let bag = DiagnosticBag::from_vec(vec![
+        Diagnostic::new("unknown `d`", find("d").span()),
+        Diagnostic::new("unknown `c`", find("c").span()),
+        Diagnostic::new("unknown `a`", find("a").span()).with_error_level(DiagnosticLevel::Warn),
+    ]);
+
+    let messages = |groups: Vec<Vec<&Diagnostic>>| {
+        groups.iter()
+            .map(|g| g.iter().map(|d| &d.message[8..]).collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(messages(bag.group_by_proximity(1)), vec!["`a`", "`c`", "`d`"]);
+    assert_eq!(messages(bag.group_by_proximity(2)), vec!["`a` `c`", "`d`"]);
+    assert_eq!(bag.group_by_proximity(4).len(), 1);
+
+    let mut out = vec![];
+    bag.write_grouped(&mut out, 2).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "warn: unknown `a`\n --> x:1:2\nerror: unknown `c`\n --> x:3:2\n\
+                1 | (a\n  |  ^ warn: unknown `a`\n2 |  b\n3 |  c)\n  |  ^ error: unknown `c`\n\n\
+                error: unknown `d`\n --> x:7:2\n7 | (d)\n\n");
+}
+
+#[test]
+fn write_colored_test() {
+    let ::Result { roots, .. } = ::simple_parse("(a b)", &[], Some("x"));
+    let bag = DiagnosticBag::from_vec(vec![
+        Diagnostic::new("bad", roots[0].span()),
+        Diagnostic::new("odd", roots[0].span()).with_error_level(DiagnosticLevel::Warn),
+    ]);
+
+    let mut plain = vec![];
+    bag.write_colored(&mut plain, ColorChoice::Never).unwrap();
+    assert_eq!(String::from_utf8(plain).unwrap(), bag.to_string());
+
+    let mut colored = vec![];
+    bag.write_colored(&mut colored, ColorChoice::Always).unwrap();
+    assert_eq!(String::from_utf8(colored).unwrap(),
+               "\x1b[1;31merror:\x1b[0m bad\n\x1b[2m -->\x1b[0m x:1:1\n\x1b[2m1 |\x1b[0m (a b)\n\n\
+                \x1b[1;33mwarn:\x1b[0m odd\n\x1b[2m -->\x1b[0m x:1:1\n\x1b[2m1 |\x1b[0m (a b)\n\n");
+    // Without the feature there is no terminal check, so `Auto` is plain
+    if cfg!(not(feature = "auto-color")) {
+        let mut auto = vec![];
+        bag.write_colored(&mut auto, ColorChoice::Auto).unwrap();
+        assert_eq!(String::from_utf8(auto).unwrap(), bag.to_string());
+    }
+}
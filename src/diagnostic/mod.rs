@@ -50,6 +50,10 @@ macro_rules! diagnostic {
     }};
 }
 
+/// How severe a diagnostic is.
+///
+/// Levels are ordered by severity, `Info < Warn < Error`.  Custom levels sort
+/// after `Error`, and among themselves by name.
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone)]
 pub enum DiagnosticLevel {
     Info,
@@ -68,6 +72,8 @@ pub struct Diagnostic {
 
     // optional
     pub min_gap: Option<usize>,
+    pub max_annotations: Option<usize>,
+    pub inline_annotations: bool,
 }
 
 #[derive(Eq, PartialEq, PartialOrd, Ord, Clone)]
@@ -96,6 +102,8 @@ impl Diagnostic {
             padding: 2,
 
             min_gap: None,
+            max_annotations: None,
+            inline_annotations: false,
             error_level: DiagnosticLevel::Error,
         }
     }
@@ -115,10 +123,63 @@ impl Diagnostic {
         self
     }
 
+    /// Limits how many annotations are rendered; the rest are summarized
+    /// with a single "... and {k} more" line.
+    pub fn with_max_annotations(mut self, max: usize) -> Diagnostic {
+        self.max_annotations = Some(max);
+        self
+    }
+
+    /// Renders annotations underneath the source line they start on.  All of
+    /// the annotations on a line share one row of carets, and are numbered
+    /// with their labels listed below it.
+    pub fn with_inline_annotations(mut self) -> Diagnostic {
+        self.inline_annotations = true;
+        self
+    }
+
+    /// Returns the annotations that will be rendered, honoring `max_annotations`.
+    fn shown_annotations(&self) -> &[DiagnosticAnnotation] {
+        match self.max_annotations {
+            Some(max) if max < self.annotations.len() => &self.annotations[..max],
+            _ => &self.annotations,
+        }
+    }
+
     pub fn add_annotation(mut self, annotation: DiagnosticAnnotation) -> Diagnostic {
         self.annotations.push(annotation);
         self
     }
+
+    /// Replaces all of this diagnostic's annotations with `annotations`.
+    pub fn with_annotations(mut self, annotations: Vec<DiagnosticAnnotation>) -> Diagnostic {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Returns true if this diagnostic has error level "Error"
+    pub fn is_error(&self) -> bool {
+        self.error_level == DiagnosticLevel::Error
+    }
+
+    /// Returns true if this diagnostic has error level "Warn"
+    pub fn is_warning(&self) -> bool {
+        self.error_level == DiagnosticLevel::Warn
+    }
+
+    /// Returns true if this diagnostic has error level "Info"
+    pub fn is_info(&self) -> bool {
+        self.error_level == DiagnosticLevel::Info
+    }
+
+    /// Returns true if this diagnostic has a custom error level
+    pub fn is_custom(&self) -> bool {
+        if let DiagnosticLevel::Custom(_) = self.error_level {
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl DiagnosticAnnotation {
@@ -138,24 +199,138 @@ impl Debug for Diagnostic {
 
 impl Display for Diagnostic {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let builder = self;
+        self.render(f, &Palette::plain())
+    }
+}
+
+/// Controls whether `DiagnosticBag::write_colored` uses ANSI colors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    /// Use colors only if stderr is a terminal.  This checks stderr no
+    /// matter which writer is being written to, so pass `Always` or `Never`
+    /// when writing somewhere else.
+    ///
+    /// Checking for a terminal needs the `auto-color` feature; without it,
+    /// `Auto` never uses colors.
+    Auto,
+}
+
+impl ColorChoice {
+    /// Resolves `Auto` into whether colors should actually be used.
+    pub fn should_color(&self) -> bool {
+        match *self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => stderr_is_terminal(),
+        }
+    }
+}
+
+#[cfg(feature = "auto-color")]
+fn stderr_is_terminal() -> bool {
+    use std::io::IsTerminal;
+    ::std::io::stderr().is_terminal()
+}
+
+#[cfg(not(feature = "auto-color"))]
+fn stderr_is_terminal() -> bool {
+    false
+}
+
+// The escape codes used while rendering a diagnostic.  The plain palette is
+// all empty strings, which is what `Display` uses.
+struct Palette {
+    level: &'static str,
+    dim: &'static str,
+    reset: &'static str,
+}
+
+impl Palette {
+    fn plain() -> Palette {
+        Palette {
+            level: "",
+            dim: "",
+            reset: "",
+        }
+    }
+
+    fn ansi(level: &DiagnosticLevel) -> Palette {
+        Palette {
+            level: match *level {
+                DiagnosticLevel::Error => "\x1b[1;31m",
+                DiagnosticLevel::Warn => "\x1b[1;33m",
+                DiagnosticLevel::Info => "\x1b[1;34m",
+                DiagnosticLevel::Custom(_) => "\x1b[1m",
+            },
+            dim: "\x1b[2m",
+            reset: "\x1b[0m",
+        }
+    }
+}
+
+// Renders a diagnostic with ANSI colors through `Display`.
+struct Colored<'a>(&'a Diagnostic);
+
+impl<'a> Display for Colored<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.render(f, &Palette::ansi(&self.0.error_level))
+    }
+}
+
+// Renders a cluster of nearby diagnostics from `DiagnosticBag::write_grouped`:
+// every diagnostic's message and location, followed by one source snippet
+// covering all of them, with each diagnostic's span drawn as an annotation.
+struct Grouped<'a>(&'a [&'a Diagnostic]);
+
+impl<'a> Display for Grouped<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let first = self.0[0];
+        if self.0.len() == 1 {
+            return first.render(f, &Palette::plain());
+        }
+
+        let mut merged = Diagnostic::new(first.message.clone(), &first.global_span);
+        merged.padding = first.padding;
+        merged.min_gap = first.min_gap;
+        for diagnostic in self.0 {
+            diagnostic.render_header(f, &Palette::plain())?;
+            merged.global_span = Span::from_spans(&merged.global_span, &diagnostic.global_span);
+            let message = format!("{}: {}", diagnostic.error_level.as_str(), diagnostic.message);
+            let annotation = DiagnosticAnnotation::new(message, diagnostic.global_span.clone());
+            merged.annotations.push(annotation);
+            merged.annotations.extend(diagnostic.annotations.iter().cloned());
+        }
+        merged.render_source(f, &Palette::plain())
+    }
+}
+
+impl Diagnostic {
+    fn render(&self, f: &mut Formatter, palette: &Palette) -> fmt::Result {
+        self.render_header(f, palette)?;
+        self.render_source(f, palette)
+    }
+
+    fn render_header(&self, f: &mut Formatter, palette: &Palette) -> fmt::Result {
         // "error" message
-        writeln!(f, "{}: {}", builder.error_level.as_str(), builder.message)?;
+        writeln!(f,
+                 "{}{}:{} {}",
+                 palette.level,
+                 self.error_level.as_str(),
+                 palette.reset,
+                 self.message)?;
 
         // File, line number, column number information
-        if let &Some(ref file) = &builder.global_span.file {
-            writeln!(f,
-                     " --> {}:{}:{}",
-                     file,
-                     builder.global_span.lines_covered.start,
-                     builder.global_span.columns.start)?;
-        } else {
-            writeln!(f,
-                     " --> {}:{}",
-                     builder.global_span.lines_covered.start,
-                     builder.global_span.columns.start)?;
-        }
+        writeln!(f,
+                 "{} -->{} {}",
+                 palette.dim,
+                 palette.reset,
+                 self.global_span.location_string())
+    }
 
+    fn render_source(&self, f: &mut Formatter, palette: &Palette) -> fmt::Result {
+        let builder = self;
         let padding = base_10_length(builder.global_span.lines_covered.end as usize +
                                      builder.global_span.lines().as_ref().lines().count());
 
@@ -167,10 +342,13 @@ impl Display for Diagnostic {
                 .enumerate()
                 .map(|(i, line)| (i + builder.global_span.lines_covered.start as usize, line));
 
+        let shown = builder.shown_annotations();
+        let mut rendered = vec![false; shown.len()];
+
         let mut skipped_streak = 0;
         for (i, line) in iter {
             let get_span = &get_span;
-            let spans = builder.annotations.iter().map(get_span);
+            let spans = builder.shown_annotations().iter().map(get_span);
             if should_skip(i,
                            skipped_streak,
                            builder.padding,
@@ -180,21 +358,160 @@ impl Display for Diagnostic {
                 skipped_streak += 1;
             } else {
                 if skipped_streak > 0 {
-                    write!(f, "{x:pd$} | ", pd = padding, x = "~")?;
+                    write_gutter(f, padding, "~", '|', palette)?;
                     writeln!(f,
                              "skipped <{}> through <{}>",
                              i - 1 - skipped_streak,
                              i - 1)?;
                 }
                 skipped_streak = 0;
-                writeln!(f, "{x:pd$} | {st}", pd = padding, x = i, st = line)?;
+                write_gutter(f, padding, i, '|', palette)?;
+                writeln!(f, "{}", line)?;
+                if builder.inline_annotations {
+                    write_inline_annotations(f, i, line, padding, palette, shown, &mut rendered)?;
+                } else {
+                    write_underlines(f, i, line, padding, palette, shown, &mut rendered)?;
+                }
             }
         }
 
+        // Annotations that fall outside of the printed lines are listed by location
+        for (annotation, _) in shown.iter().zip(rendered).filter(|&(_, drawn)| !drawn) {
+            write_gutter(f, padding, "", '=', palette)?;
+            writeln!(f, "{}: {}", annotation.span.location_string(), annotation.message)?;
+        }
+        let hidden = builder.annotations.len() - shown.len();
+        if hidden > 0 {
+            write_gutter(f, padding, "", '=', palette)?;
+            writeln!(f, "... and {} more", hidden)?;
+        }
+
         Ok(())
     }
 }
 
+// Writes a row of carets under `line` for every annotation that covers it.
+// Annotations that span several lines are underlined on each of them, and
+// their message goes after the underline on the last one.  A zero-width
+// annotation gets a single caret.
+fn write_underlines(f: &mut Formatter,
+                    line_number: usize,
+                    line: &str,
+                    padding: usize,
+                    palette: &Palette,
+                    annotations: &[DiagnosticAnnotation],
+                    rendered: &mut [bool])
+                    -> fmt::Result {
+    let mut on_line: Vec<_> = annotations.iter()
+        .enumerate()
+        .filter(|&(_, a)| {
+            a.span.lines_covered.start as usize <= line_number &&
+            line_number <= a.span.lines_covered.end as usize
+        })
+        .map(|(index, annotation)| {
+            let span = &annotation.span;
+            let start = if span.lines_covered.start as usize == line_number {
+                span.columns.start.saturating_sub(1) as usize
+            } else {
+                line.len() - line.trim_start().len()
+            };
+            let end = if span.lines_covered.end as usize == line_number {
+                span.columns.end.saturating_sub(1) as usize
+            } else {
+                line.len()
+            };
+            (start, end, index, annotation)
+        })
+        .collect();
+    on_line.sort_by_key(|&(start, _, index, _)| (start, index));
+
+    for (start, end, index, annotation) in on_line {
+        let mut row = String::new();
+        pad_to(&mut row, start);
+        row.extend(::std::iter::repeat('^').take(::std::cmp::max(end, start + 1) - start));
+        if annotation.span.lines_covered.end as usize == line_number {
+            rendered[index] = true;
+            row.push(' ');
+            row.push_str(&annotation.message);
+        }
+        write_gutter(f, padding, "", '|', palette)?;
+        writeln!(f, "{}", row)?;
+    }
+    Ok(())
+}
+
+// Writes one row of carets under `line` for every annotation that starts on
+// it, a row of numbers marking where each one starts, and then their labels.
+fn write_inline_annotations(f: &mut Formatter,
+                            line_number: usize,
+                            line: &str,
+                            padding: usize,
+                            palette: &Palette,
+                            annotations: &[DiagnosticAnnotation],
+                            rendered: &mut [bool])
+                            -> fmt::Result {
+    let on_line: Vec<_> = annotations.iter()
+        .enumerate()
+        .filter(|&(_, a)| a.span.lines_covered.start as usize == line_number)
+        .collect();
+    if on_line.is_empty() {
+        return Ok(());
+    }
+
+    let mut carets = String::new();
+    let mut numbers = String::new();
+    for &(index, annotation) in &on_line {
+        rendered[index] = true;
+        let start = annotation.span.columns.start.saturating_sub(1) as usize;
+        let end = if annotation.span.lines_covered.end == annotation.span.lines_covered.start {
+            annotation.span.columns.end.saturating_sub(1) as usize
+        } else {
+            line.len()
+        };
+        // Overlapping annotations keep whatever was drawn first
+        if carets.len() <= start {
+            pad_to(&mut carets, start);
+            carets.extend(::std::iter::repeat('^').take(::std::cmp::max(end, start + 1) - start));
+        }
+        if numbers.len() <= start {
+            pad_to(&mut numbers, start);
+            numbers.push_str(&(index + 1).to_string());
+        }
+    }
+
+    write_gutter(f, padding, "", '|', palette)?;
+    writeln!(f, "{}", carets)?;
+    write_gutter(f, padding, "", '|', palette)?;
+    writeln!(f, "{}", numbers)?;
+    for &(index, annotation) in &on_line {
+        write_gutter(f, padding, "", '|', palette)?;
+        writeln!(f, "{}: {}", index + 1, annotation.message)?;
+    }
+    Ok(())
+}
+
+// Writes the `N | ` gutter that starts each source and annotation line.
+fn write_gutter<T: Display>(f: &mut Formatter,
+                            padding: usize,
+                            label: T,
+                            separator: char,
+                            palette: &Palette)
+                            -> fmt::Result {
+    write!(f,
+           "{dim}{x:pd$} {sep}{reset} ",
+           dim = palette.dim,
+           x = label,
+           pd = padding,
+           sep = separator,
+           reset = palette.reset)
+}
+
+fn pad_to(row: &mut String, len: usize) {
+    while row.len() < len {
+        row.push(' ');
+    }
+}
+
 fn get_span<'a>(ann: &'a DiagnosticAnnotation) -> &'a Span {
     &ann.span
 }
@@ -255,6 +572,17 @@ fn line_dist_all<'a, I>(line: usize, i: I) -> Option<usize>
     i.map(|s| line_distance(line, s)).min()
 }
 
+// Returns how many lines apart two spans are, or 0 if they share a line.
+fn span_distance(a: &Span, b: &Span) -> usize {
+    if a.lines_covered.start <= b.lines_covered.end &&
+       b.lines_covered.start <= a.lines_covered.end {
+        0
+    } else {
+        ::std::cmp::min(line_distance(a.lines_covered.start as usize, b),
+                        line_distance(a.lines_covered.end as usize, b))
+    }
+}
+
 // Return the distance to
 fn line_distance(line: usize, span: &Span) -> usize {
 
@@ -282,7 +610,7 @@ fn diagnostic_macros() {
             (map (cdr xs) f)))))
 "#;
 
-    let Result { roots, diagnostics } = ::simple_parse(source, &[], Some("<anon>"));
+    let Result { roots, diagnostics, .. } = ::simple_parse(source, &[], Some("<anon>"));
     let span = &roots[0].span();
     assert!(diagnostics.is_empty());
 
@@ -303,7 +631,7 @@ fn test_basic_error() {
             (map (cdr xs) f)))))
 "#;
 
-    let Result { roots, diagnostics } = ::simple_parse(source, &[], Some("<anon>"));
+    let Result { roots, diagnostics, .. } = ::simple_parse(source, &[], Some("<anon>"));
     assert!(diagnostics.is_empty());
 
     let error = Diagnostic::new("this is the message", roots[0].span())
@@ -318,3 +646,114 @@ fn test_basic_error() {
 3 |       (cons (f (car xs))
 4 |             (map (cdr xs) f)))))"#);
 }
+
+#[test]
+fn level_predicates() {
+    let span = Span::empty();
+
+    let error = diagnostic!(ERROR, &span, "e");
+    assert!(error.is_error() && !error.is_warning() && !error.is_info() && !error.is_custom());
+
+    let warning = diagnostic!(WARN, &span, "w");
+    assert!(!warning.is_error() && warning.is_warning() && !warning.is_info() && !warning.is_custom());
+
+    let info = diagnostic!(INFO, &span, "i");
+    assert!(!info.is_error() && !info.is_warning() && info.is_info() && !info.is_custom());
+
+    let custom = diagnostic!(CUSTOM("lint".into()), &span, "c");
+    assert!(!custom.is_error() && !custom.is_warning() && !custom.is_info() && custom.is_custom());
+}
+
+#[test]
+fn with_annotations() {
+    let Result { roots, .. } = ::simple_parse("(a b c)", &[], None);
+    let children = roots[0].expect_list(&mut ::diagnostic::DiagnosticBag::new()).unwrap();
+    let annotations: Vec<_> = children.iter()
+        .map(|c| DiagnosticAnnotation::new(format!("used `{}` here", c.text()), c.span().clone()))
+        .collect();
+
+    let diagnostic = Diagnostic::new("message", roots[0].span())
+        .with_annotations(annotations.clone());
+    let chained = annotations.into_iter()
+        .fold(Diagnostic::new("message", roots[0].span()), Diagnostic::add_annotation);
+
+    assert_eq!(diagnostic.annotations.len(), 3);
+    assert_eq!(diagnostic.annotations[2].message, "used `c` here");
+    assert_eq!(diagnostic, chained);
+}
+
+#[test]
+fn max_annotations() {
+    let source = "a b c d e f g h i j";
+    let Result { roots, .. } = ::simple_parse(source, &[], None);
+    let span: Span = roots.iter().map(|r| r.span()).collect();
+    let annotations = roots.iter()
+        .map(|r| DiagnosticAnnotation::new(format!("`{}` is unused", r.text()), r.span().clone()))
+        .collect();
+    let diagnostic = Diagnostic::new("unused values", &span).with_annotations(annotations);
+
+    let rendered = diagnostic.to_string();
+    assert!(rendered.contains("  |                   ^ `j` is unused"));
+    assert!(!rendered.contains("more"));
+
+    let rendered = diagnostic.with_max_annotations(3).to_string();
+    assert_eq!(rendered.trim(),
+               r#"error: unused values
+ --> 1:1
+1 | a b c d e f g h i j
+  | ^ `a` is unused
+  |   ^ `b` is unused
+  |     ^ `c` is unused
+  = ... and 7 more"#);
+}
+
+#[test]
+fn inline_annotations() {
+    let Result { roots, .. } = ::simple_parse("(foo bar\n  baz)", &[], None);
+    let children = roots[0].expect_list(&mut ::diagnostic::DiagnosticBag::new()).unwrap();
+    let diagnostic = Diagnostic::new("message", roots[0].span())
+        .add_annotation(DiagnosticAnnotation::new("first".into(), children[0].span().clone()))
+        .add_annotation(DiagnosticAnnotation::new("second".into(), children[1].span().clone()))
+        .add_annotation(DiagnosticAnnotation::new("third".into(), children[2].span().clone()))
+        .with_inline_annotations();
+
+    assert_eq!(diagnostic.to_string().trim(),
+               r#"error: message
+ --> 1:1
+1 | (foo bar
+  |  ^^^ ^^^
+  |  1   2
+  | 1: first
+  | 2: second
+2 |   baz)
+  |   ^^^
+  |   3
+  | 3: third"#);
+}
+
+#[test]
+fn underlined_annotations() {
+    let Result { roots, .. } = ::simple_parse("(foo bar\n  baz)", &[], None);
+    let children = roots[0].expect_list(&mut ::diagnostic::DiagnosticBag::new()).unwrap();
+    let mut point = children[2].span().clone();
+    point.columns.end = point.columns.start;
+    point.text_bytes.end = point.text_bytes.start;
+
+    let spanning: Span = children[1..].iter().map(|c| c.span()).collect();
+    let diagnostic = Diagnostic::new("message", roots[0].span())
+        .add_annotation(DiagnosticAnnotation::new("first".into(), children[0].span().clone()))
+        .add_annotation(DiagnosticAnnotation::new("second".into(), children[1].span().clone()))
+        .add_annotation(DiagnosticAnnotation::new("both".into(), spanning))
+        .add_annotation(DiagnosticAnnotation::new("point".into(), point));
+
+    assert_eq!(diagnostic.to_string().trim(),
+               r#"error: message
+ --> 1:1
+1 | (foo bar
+  |  ^^^ first
+  |      ^^^ second
+  |      ^^^
+2 |   baz)
+  |   ^^^ both
+  |   ^ point"#);
+}
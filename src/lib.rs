@@ -6,16 +6,23 @@ extern crate serde_json;
 #[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde;
+#[cfg(feature = "num-bigint")]
+extern crate num_bigint;
+#[cfg(feature = "num-rational")]
+extern crate num_rational;
 
 pub mod token;
 pub mod parse;
 #[macro_use]
 pub mod diagnostic;
 pub mod serde_serialization;
+pub mod cst;
 mod sexpr;
 
-pub use sexpr::Sexpr;
+pub use sexpr::{Descendants, Number, PrettyOptions, Sexpr, SexprFold, SexprVisitor,
+                StructuralSexpr, Visit, pretty_print};
 
 /// The result of a text parse.
 ///
@@ -28,12 +35,125 @@ pub struct Result {
     ///
     /// All parse errors in the bag are ErrorLevel::Error.
     pub diagnostics: diagnostic::DiagnosticBag,
+    // The source text that was parsed; read it with `source()`
+    source: Option<tendril::StrTendril>,
+    // The name of the file that was parsed; read it with `file()`
+    file: Option<std::rc::Rc<String>>,
+}
+
+impl Result {
+    /// Returns a mutable reference to the diagnostics collected during parse.
+    ///
+    /// Useful for adding diagnostics from later passes (like semantic analysis)
+    /// into the same bag that the parser used.
+    pub fn diagnostics_mut(&mut self) -> &mut diagnostic::DiagnosticBag {
+        &mut self.diagnostics
+    }
+
+    /// Adds a diagnostic to the diagnostics collected during parse.
+    pub fn add_diagnostic(&mut self, diagnostic: diagnostic::Diagnostic) {
+        self.diagnostics.add(diagnostic);
+    }
+
+    /// Returns true if there are no error-level diagnostics.
+    pub fn is_ok(&self) -> bool {
+        !self.has_errors()
+    }
+
+    /// Returns true if there are any error-level diagnostics.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.contains_errors()
+    }
+
+    /// Returns true if there are any warning-level diagnostics.
+    pub fn has_warnings(&self) -> bool {
+        self.diagnostics.contains_warnings()
+    }
+
+    /// Returns the diagnostics collected during parse as JSON, in the format
+    /// of `DiagnosticBag::to_json`.
+    pub fn diagnostics_json(&self) -> serde_json::Value {
+        self.diagnostics.to_json()
+    }
+
+    /// Returns the full source text that was parsed, even if it produced no roots.
+    pub fn source(&self) -> Option<tendril::StrTendril> {
+        self.source.clone()
+    }
+
+    /// Returns the name of the file that was parsed, if one was given.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_ref().map(|f| f.as_str())
+    }
+
+    /// Builds a `SourceMap` of the parsed source, for fast line and column
+    /// lookups.
+    pub fn source_map(&self) -> parse::SourceMap {
+        parse::SourceMap::new(self.source.as_ref().unwrap_or(&tendril::StrTendril::new()))
+    }
+
+    /// Returns the span of each top-level form, in source order.
+    pub fn root_spans(&self) -> Vec<&parse::Span> {
+        self.roots.iter().map(Sexpr::span).collect()
+    }
+
+    /// Returns the top-level forms for which `pred` returns true, in source order.
+    pub fn filter_roots<F: Fn(&Sexpr) -> bool>(&self, pred: F) -> Vec<&Sexpr> {
+        self.roots.iter().filter(|r| pred(r)).collect()
+    }
+
+    /// Returns the roots if the parse produced no errors, or a `ParseError`
+    /// holding every diagnostic otherwise.
+    pub fn into_result(self) -> std::result::Result<Vec<Sexpr>, ParseError> {
+        if self.has_errors() {
+            Err(ParseError { diagnostics: self.diagnostics })
+        } else {
+            Ok(self.roots)
+        }
+    }
+}
+
+/// The error produced by `Result::into_result` and `simple_parse_one`.
+///
+/// Implements `std::error::Error`, so it can be propagated with `?`.
+/// Displaying it displays every diagnostic in the bag.
+#[derive(Debug)]
+pub struct ParseError {
+    pub diagnostics: diagnostic::DiagnosticBag,
+}
+
+impl From<diagnostic::DiagnosticBag> for ParseError {
+    fn from(diagnostics: diagnostic::DiagnosticBag) -> ParseError {
+        ParseError { diagnostics: diagnostics }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}", self.diagnostics)
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn description(&self) -> &str {
+        "failed to parse"
+    }
 }
 
 /// Parses some text with the builtin tokenizer.
 ///
+/// The input is converted into a `StrTendril` once (which copies a `&str`
+/// into a shared buffer); every `Span` in the result then refers to that same
+/// buffer, so no further copies of the source are made.
+///
+/// That one copy can't be avoided.  A `StrTendril` always owns its bytes, so
+/// spans can't borrow from memory owned by someone else, such as an mmap.
+///
 /// `splitters` is a list of strings that should be split on the tokenization level.
 /// As an example: [":"] will make "foo:bar" split into ["foo", ":", "bar"] during tokenization.
+///
+/// Panics if the input is longer than `u32::MAX` bytes, because a `StrTendril`
+/// can't hold it.
 pub fn simple_parse<'a, S: Into<tendril::StrTendril>>(string: S,
                                                       splitters: &'a [&'a str],
                                                       file: Option<&'a str>)
@@ -42,3 +162,149 @@ pub fn simple_parse<'a, S: Into<tendril::StrTendril>>(string: S,
     let tokens = token::tokenize(tendril.clone(), splitters);
     parse::parse(&tendril, tokens, file.map(String::from))
 }
+
+/// Parses text that should contain exactly one expression.
+///
+/// Fails if the parse produced errors, or if there were zero or several
+/// top-level expressions.
+pub fn simple_parse_one<'a, S: Into<tendril::StrTendril>>(string: S,
+                                                          splitters: &'a [&'a str],
+                                                          file: Option<&'a str>)
+                                                          -> std::result::Result<Sexpr, ParseError> {
+    let mut roots = simple_parse(string, splitters, file).into_result()?;
+    match roots.len() {
+        1 => Ok(roots.pop().unwrap()),
+        0 => {
+            let diagnostic = diagnostic!(&parse::Span::empty(), "expected an expression but found none");
+            Err(diagnostic::DiagnosticBag::singleton(diagnostic).into())
+        }
+        n => {
+            let span: parse::Span = roots.iter().map(Sexpr::span).collect();
+            let diagnostic = diagnostic!(&span, "expected one expression but found {}", n);
+            Err(diagnostic::DiagnosticBag::singleton(diagnostic).into())
+        }
+    }
+}
+
+#[test]
+fn add_diagnostic_after_parse() {
+    let mut result = simple_parse("(foo bar)", &[], Some("<anon>"));
+    assert!(result.diagnostics.is_empty());
+
+    let span = result.roots[0].span().clone();
+    result.add_diagnostic(diagnostic!(WARN, &span, "unused form"));
+    result.diagnostics_mut().add(diagnostic!(&span, "unknown function `foo`"));
+
+    assert_eq!(result.diagnostics.len(), 2);
+    assert_eq!(result.diagnostics.to_string(),
+               r#"warn: unused form
+ --> <anon>:1:1
+1 | (foo bar)
+
+error: unknown function `foo`
+ --> <anon>:1:1
+1 | (foo bar)
+
+"#);
+}
+
+#[test]
+fn diagnostics_json_test() {
+    let result = simple_parse("(a b", &[], Some("file"));
+    assert!(result.has_errors());
+    assert_eq!(result.diagnostics_json(), result.diagnostics.to_json());
+}
+
+#[test]
+fn root_spans_test() {
+    let result = simple_parse("(foo 1)\n(bar 2 3)", &[], None);
+    assert!(result.diagnostics.is_empty());
+
+    let spans = result.root_spans();
+    assert_eq!(spans.len(), 2);
+    assert_eq!(spans[0].text().as_ref(), "(foo 1)");
+    assert_eq!(spans[1].text().as_ref(), "(bar 2 3)");
+    assert!(spans[0].text_bytes.end <= spans[1].text_bytes.start);
+}
+
+#[test]
+fn spans_share_source_buffer() {
+    fn visit(sexpr: &Sexpr, ptr: *const u8) {
+        assert_eq!(sexpr.span().full_text.as_ptr(), ptr);
+        if let &Sexpr::List { ref children, .. } = sexpr {
+            for child in children {
+                visit(child, ptr);
+            }
+        }
+    }
+
+    let input: &str = "(hello (world foo) bar)";
+    let result = simple_parse(input, &[], None);
+    assert!(result.diagnostics.is_empty());
+
+    // The input is copied once, and every span shares that copy
+    let source = result.source().unwrap();
+    assert!(source.as_ptr() != input.as_ptr());
+    for root in &result.roots {
+        visit(root, source.as_ptr());
+    }
+}
+
+#[test]
+fn source_test() {
+    let input = "(foo bar)\n(baz)";
+    let result = simple_parse(input, &[], None);
+    assert_eq!(result.source().unwrap().as_ref(), input);
+
+    let result = simple_parse("", &[], None);
+    assert!(result.roots.is_empty());
+    assert_eq!(result.source().unwrap().as_ref(), "");
+    assert_eq!(result.file(), None);
+    assert_eq!(simple_parse(input, &[], Some("a.snoot")).file(), Some("a.snoot"));
+}
+
+#[test]
+fn result_predicates() {
+    let clean = simple_parse("(a b)", &[], None);
+    assert!(clean.is_ok() && !clean.has_errors() && !clean.has_warnings());
+
+    let mut warned = simple_parse("(a b)", &[], None);
+    let span = warned.roots[0].span().clone();
+    warned.add_diagnostic(diagnostic!(WARN, &span, "suspicious"));
+    assert!(warned.is_ok() && !warned.has_errors() && warned.has_warnings());
+
+    let errored = simple_parse("(a b", &[], None);
+    assert!(!errored.is_ok() && errored.has_errors() && !errored.has_warnings());
+}
+
+#[test]
+fn filter_roots_test() {
+    let result = simple_parse("(def a 1) (print a) (def b 2) def", &[], None);
+    let is_def = |sexpr: &Sexpr| match sexpr {
+        &Sexpr::List { ref children, .. } => {
+            children.first().map_or(false, |c| c.text().as_ref() == "def")
+        }
+        _ => false,
+    };
+
+    let defs: Vec<_> = result.filter_roots(is_def).iter().map(|r| r.text().to_string()).collect();
+    assert_eq!(defs, vec!["(def a 1)", "(def b 2)"]);
+}
+
+#[test]
+fn parse_error_with_question_mark() {
+    fn load(input: &str) -> std::result::Result<Sexpr, Box<dyn std::error::Error>> {
+        Ok(simple_parse_one(input, &[], Some("config"))?)
+    }
+
+    assert_eq!(load("(a b)").unwrap().text().as_ref(), "(a b)");
+
+    let error = load("(a b").unwrap_err();
+    assert!(error.to_string().starts_with("error: unclosed list\n --> config:1:1"));
+
+    let error = load("(a) (b)").unwrap_err();
+    assert!(error.to_string().starts_with("error: expected one expression but found 2"));
+    assert!(load("").is_err());
+
+    assert_eq!(simple_parse("(a) (b)", &[], None).into_result().unwrap().len(), 2);
+}
@@ -1,15 +1,19 @@
 use std::rc::Rc;
 
 use super::token::*;
-use super::diagnostic::{Diagnostic, DiagnosticLevel};
+use super::diagnostic::{Diagnostic, DiagnosticBag, DiagnosticLevel};
 use tendril::StrTendril;
 use {Result, Sexpr};
 
 mod scopestack;
+mod recovery;
+mod source_map;
 pub mod test;
 pub mod simplified_test;
 
 use self::scopestack::ScopeStack;
+pub use self::recovery::{DefaultRecovery, Recovery, RecoveryStrategy};
+pub use self::source_map::SourceMap;
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy, Ord, PartialOrd)]
 pub struct StartEnd {
@@ -32,15 +36,76 @@ pub struct Span {
 pub enum SexprKind {
     List,
     UnaryOperator,
+    WithMeta,
     Terminal,
     String,
 }
 
+impl SexprKind {
+    /// Returns a lowercase, human-readable name for this kind, for use in
+    /// user-facing messages.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SexprKind::List => "list",
+            SexprKind::UnaryOperator => "unary operator",
+            SexprKind::WithMeta => "expression with metadata",
+            SexprKind::Terminal => "terminal",
+            SexprKind::String => "string",
+        }
+    }
+}
+
+/// Options that change how tokens are assembled into s-expressions.
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    /// When true, a `^` terminal attaches the form following it as metadata
+    /// to the form after that, producing `Sexpr::WithMeta`.
+    ///
+    /// Add `"^"` to the tokenizer splitters so that `^foo` is split into `^` and `foo`.
+    pub metadata: bool,
+    /// The deepest that lists, unary operators and metadata forms may be
+    /// nested.  A list that would go deeper is reported as
+    /// `ParseDiagnostic::DepthLimitExceeded` and skipped, along with
+    /// everything inside it; an operator or `^` that would go deeper is
+    /// skipped on its own.  Unlimited by default; set this when parsing
+    /// untrusted input.
+    pub max_depth: Option<usize>,
+    /// When true, string literals separated only by whitespace are merged
+    /// into one `Sexpr::String` covering all of them, like `"a" "b"` in C.
+    /// `unescape_string` decodes the merged text as the concatenation.
+    pub concat_strings: bool,
+}
+
+impl ParseOptions {
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    pub fn with_metadata(mut self, metadata: bool) -> ParseOptions {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> ParseOptions {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_concat_strings(mut self, concat_strings: bool) -> ParseOptions {
+        self.concat_strings = concat_strings;
+        self
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseDiagnostic {
     TokenizationError(TokError),
     UnclosedList(Span),
-    ExtraClosing(Span),
+    ExtraClosing { span: Span, count: usize },
+    FileTooLarge(Span),
+    MetadataWithNoTarget(Span),
+    UnaryOpWithNoArgument(Span),
+    DepthLimitExceeded(Span),
     WrongClosing {
         opening_span: Span,
         closing_span: Span,
@@ -52,13 +117,46 @@ pub enum ParseDiagnostic {
 impl ParseDiagnostic {
     pub fn into_diagnostic(self) -> Diagnostic {
         match self {
-            ParseDiagnostic::TokenizationError(TokError::UnclosedString(_span)) => {
-                unreachable!();
+            ParseDiagnostic::TokenizationError(TokError::UnclosedString(span)) => {
+                let builder = Diagnostic::new("unclosed string", &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::TokenizationError(TokError::AtomTooLong { span, max }) => {
+                let text = format!("atom exceeds {} characters", max);
+                let builder = Diagnostic::new(text, &span);
+                builder.with_error_level(DiagnosticLevel::Error)
             }
-            ParseDiagnostic::ExtraClosing(span) => {
+            ParseDiagnostic::TokenizationError(TokError::Io(message)) => {
+                let text = format!("could not read input: {}", message);
+                let builder = Diagnostic::new(text, &Span::empty());
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::ExtraClosing { span, count: 1 } => {
                 let builder = Diagnostic::new("extra list closing", &span);
                 builder.with_error_level(DiagnosticLevel::Error)
             }
+            ParseDiagnostic::ExtraClosing { span, count } => {
+                let text = format!("{} extra closing delimiters", count);
+                let builder = Diagnostic::new(text, &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::FileTooLarge(span) => {
+                let builder = Diagnostic::new("file too large for u32 offsets", &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::DepthLimitExceeded(span) => {
+                let builder = Diagnostic::new("nesting exceeds the depth limit", &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::MetadataWithNoTarget(span) => {
+                let builder = Diagnostic::new("metadata with no following expression", &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
+            ParseDiagnostic::UnaryOpWithNoArgument(span) => {
+                let text = format!("unary operator `{}` with no argument", span.text());
+                let builder = Diagnostic::new(text, &span);
+                builder.with_error_level(DiagnosticLevel::Error)
+            }
             ParseDiagnostic::UnclosedList(span) => {
                 let builder = Diagnostic::new("unclosed list", &span);
                 builder.with_error_level(DiagnosticLevel::Error)
@@ -81,11 +179,22 @@ impl ParseDiagnostic {
 }
 
 
+// Returns the 1-based UTF-16 column of the byte offset `pos` in `text`, or
+// `None` if `pos` isn't a char boundary inside `text`.
+fn utf16_column(text: &str, pos: u32) -> Option<u32> {
+    if !text.is_char_boundary(pos as usize) {
+        return None;
+    }
+    let before = &text[..pos as usize];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some(before[line_start..].encode_utf16().count() as u32 + 1)
+}
+
 fn find_newline(t: &[u8], mut pos: u32, direction: isize) -> u32 {
     loop {
         // We're searching backwards and we've hit the start of the buffer
         if pos == 0 && direction == -1 {
-            if t[0] == b'\n' {
+            if t.first() == Some(&b'\n') {
                 return 1;
             } else {
                 return 0;
@@ -145,6 +254,73 @@ impl Span {
         self.full_text.subtendril(start, end - start)
     }
 
+    /// Returns the name of the file that this span is in, if there is one.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file.as_ref().map(|f| f.as_str())
+    }
+
+    /// Returns the start of this span as `file:line:col`, or `line:col` when
+    /// there is no file.  This is the location shown on a diagnostic's `-->` line.
+    pub fn location_string(&self) -> String {
+        match self.file_name() {
+            Some(file) => format!("{}:{}:{}", file, self.lines_covered.start, self.columns.start),
+            None => format!("{}:{}", self.lines_covered.start, self.columns.start),
+        }
+    }
+
+    /// Returns the start and end columns of this span counted in UTF-16 code
+    /// units rather than bytes, as the Language Server Protocol expects.
+    ///
+    /// Like `columns`, these are 1-based and the end is exclusive.  If
+    /// `full_text` is empty or doesn't hold `text_bytes`, there is nothing
+    /// to count, so this returns `columns` unchanged.
+    pub fn utf16_columns(&self) -> StartEnd {
+        if self.full_text.is_empty() {
+            return self.columns;
+        }
+        match (utf16_column(&self.full_text, self.text_bytes.start),
+               utf16_column(&self.full_text, self.text_bytes.end)) {
+            (Some(start), Some(end)) => StartEnd { start: start, end: end },
+            _ => self.columns,
+        }
+    }
+
+    /// Returns true if the byte at `offset` falls inside this span.
+    pub fn contains_byte(&self, offset: u32) -> bool {
+        self.text_bytes.start <= offset && offset < self.text_bytes.end
+    }
+
+    /// Returns true if `other` lies entirely inside this span, in the same file.
+    pub fn contains(&self, other: &Span) -> bool {
+        self.file == other.file && self.text_bytes.start <= other.text_bytes.start &&
+        other.text_bytes.end <= self.text_bytes.end
+    }
+
+    /// Returns true if this span and `other` share at least one byte, in the same file.
+    pub fn intersects(&self, other: &Span) -> bool {
+        self.file == other.file && self.text_bytes.start < other.text_bytes.end &&
+        other.text_bytes.start < self.text_bytes.end
+    }
+
+    /// Returns the byte range of this span relative to the start of `base`,
+    /// or `None` if this span is not contained within `base`.
+    pub fn relative_to(&self, base: &Span) -> Option<StartEnd> {
+        if self.file != base.file || self.text_bytes.start < base.text_bytes.start ||
+           self.text_bytes.end > base.text_bytes.end {
+            return None;
+        }
+
+        Some(StartEnd {
+                 start: self.text_bytes.start - base.text_bytes.start,
+                 end: self.text_bytes.end - base.text_bytes.start,
+             })
+    }
+
+    /// Builds the span covered by a single token.
+    ///
+    /// Line and column information is copied from the token rather than
+    /// recomputed from the source, so this is constant-time.  The only
+    /// scanning of the source happens lazily, in `lines()`.
     pub fn from_token(token: &TokenInfo, string: &StrTendril, file: &Option<Rc<String>>) -> Span {
         let chars = string
             .subtendril(token.byte_offset as u32, token.length)
@@ -169,14 +345,26 @@ impl Span {
         }
     }
 
-    pub fn from_spans(start: &Span, end: &Span) -> Span {
-        let (start, end) = if start.text_bytes.start < end.text_bytes.start {
-            (start, end)
-        } else {
-            (end, start)
-        };
+    /// Rebuilds the span of a token from the source and file of the parse
+    /// that produced it, for tools that only kept the `TokenInfo`.
+    pub fn for_token(token: &TokenInfo, source: &Result) -> Span {
+        let string = source.source.clone().unwrap_or_else(StrTendril::new);
+        Span::from_token(token, &string, &source.file)
+    }
+
+    /// Builds a span that covers both `a` and `b` and everything between.
+    ///
+    /// Like `from_token`, this is constant-time.
+    ///
+    /// The arguments may be given in either order, and either one may
+    /// contain the other.
+    pub fn from_spans(a: &Span, b: &Span) -> Span {
+        debug_assert!(a.file == b.file);
 
-        debug_assert!(start.file == end.file);
+        // The start and end are chosen independently, since one span may
+        // start first while the other ends last.
+        let start = if a.text_bytes.start <= b.text_bytes.start { a } else { b };
+        let end = if a.text_bytes.end >= b.text_bytes.end { a } else { b };
 
         Span {
             full_text: start.full_text.clone(),
@@ -198,57 +386,374 @@ impl Span {
     }
 }
 
-pub fn parse<I>(string: &StrTendril, mut tokens: I, file: Option<String>) -> Result
+/// Whether a buffer holds complete expressions, as reported by `is_complete`.
+#[derive(Debug)]
+pub enum Completeness {
+    /// Every list that was opened has been closed.
+    Complete,
+    /// More input is needed; holds the number of lists that are still open.
+    Incomplete(usize),
+    /// No amount of further input can fix the buffer, for example because of
+    /// an extra or mismatched closing delimiter.
+    Invalid(Diagnostic),
+}
+
+/// Checks whether `input` is balanced, for deciding whether a REPL should
+/// evaluate the buffer or prompt for another line.
+///
+/// A string literal that is still open at the end of the input also makes
+/// the buffer incomplete.
+pub fn is_complete(input: &str, splitters: &[&str]) -> Completeness {
+    let string: StrTendril = input.into();
+    let span = |token: &TokenInfo| Span::from_token(token, &string, &None);
+    let mut open: Vec<TokenInfo> = vec![];
+
+    for token in tokenize(string.clone(), splitters) {
+        let token = match token {
+            Ok(token) => token,
+            Err(TokError::UnclosedString(_)) => return Completeness::Incomplete(open.len()),
+            Err(TokError::AtomTooLong { .. }) |
+            Err(TokError::Io(_)) => continue,
+        };
+
+        match token.typ {
+            TokenType::ListOpening(_) => open.push(token),
+            TokenType::ListClosing(actual) => {
+                let opening = match open.pop() {
+                    Some(opening) => opening,
+                    None => {
+                        let diagnostic = ParseDiagnostic::ExtraClosing {
+                            span: span(&token),
+                            count: 1,
+                        };
+                        return Completeness::Invalid(diagnostic.into_diagnostic());
+                    }
+                };
+                match opening.typ {
+                    TokenType::ListOpening(expected) if expected != actual => {
+                        let diagnostic = ParseDiagnostic::WrongClosing {
+                            opening_span: span(&opening),
+                            closing_span: span(&token),
+                            expected_list_type: expected,
+                            actual_list_type: actual,
+                        };
+                        return Completeness::Invalid(diagnostic.into_diagnostic());
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if open.is_empty() {
+        Completeness::Complete
+    } else {
+        Completeness::Incomplete(open.len())
+    }
+}
+
+/// Returns the 1-based line and column of the byte at `byte` in `full_text`.
+///
+/// Columns are counted the same way the tokenizer counts them for
+/// `Span::columns`: one per byte, so a multibyte character takes up several
+/// columns.  An offset past the end of the text is treated as the end.
+pub fn byte_to_line_col(full_text: &StrTendril, byte: u32) -> (u32, u32) {
+    let before = &full_text.as_bytes()[..::std::cmp::min(byte as usize, full_text.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count() as u32 + 1;
+    let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    (line, (before.len() - line_start) as u32 + 1)
+}
+
+/// Returns the byte offset in `full_text` of a 1-based line and column, as
+/// counted by `byte_to_line_col`.
+///
+/// A column past the end of its line is clamped to the end of the line.
+/// Returns `None` if the text doesn't have that many lines.
+pub fn line_col_to_byte(full_text: &StrTendril, line: u32, column: u32) -> Option<u32> {
+    if line == 0 {
+        return None;
+    }
+    let bytes = full_text.as_bytes();
+    let mut line_start = 0;
+    for _ in 1..line {
+        match bytes[line_start..].iter().position(|&b| b == b'\n') {
+            Some(i) => line_start += i + 1,
+            None => return None,
+        }
+    }
+    let line_end = find_newline(bytes, line_start as u32, 1);
+    let column = ::std::cmp::max(column, 1) - 1;
+    Some(::std::cmp::min(line_start as u32 + column, line_end))
+}
+
+fn into_bag(diagnostics: Vec<ParseDiagnostic>) -> DiagnosticBag {
+    diagnostics.into_iter().map(ParseDiagnostic::into_diagnostic).collect()
+}
+
+pub fn parse<I>(string: &StrTendril, tokens: I, file: Option<String>) -> Result
+    where I: Iterator<Item = TokResult<TokenInfo>>
+{
+    parse_with_options(string, tokens, file, &ParseOptions::default())
+}
+
+/// Parses a token stream like `parse`, with non-default `ParseOptions`.
+pub fn parse_with_options<I>(string: &StrTendril,
+                             tokens: I,
+                             file: Option<String>,
+                             options: &ParseOptions)
+                             -> Result
     where I: Iterator<Item = TokResult<TokenInfo>>
+{
+    parse_with_recovery(string, tokens, file, options, &mut DefaultRecovery)
+}
+
+/// Parses a token stream like `parse_with_options`, consulting `recovery`
+/// whenever list delimiters are missing or mismatched.
+pub fn parse_with_recovery<I, R>(string: &StrTendril,
+                                 tokens: I,
+                                 file: Option<String>,
+                                 options: &ParseOptions,
+                                 recovery: &mut R)
+                                 -> Result
+    where I: Iterator<Item = TokResult<TokenInfo>>,
+          R: RecoveryStrategy
+{
+    parse_with_max_len(string,
+                       tokens,
+                       file,
+                       options,
+                       recovery,
+                       ::std::u32::MAX as usize)
+}
+
+// Spans store byte offsets as u32, so larger inputs would produce corrupt spans.
+//
+// With the default `u32::MAX` limit this check is effectively only reachable
+// through the smaller limits used in tests: a `StrTendril` can't hold more
+// than `u32::MAX` bytes, and building one from a larger `&str` or `String`
+// panics before parsing starts.
+fn parse_with_max_len<I, R>(string: &StrTendril,
+                            mut tokens: I,
+                            file: Option<String>,
+                            options: &ParseOptions,
+                            recovery: &mut R,
+                            max_len: usize)
+                            -> Result
+    where I: Iterator<Item = TokResult<TokenInfo>>,
+          R: RecoveryStrategy
 {
     let file = file.map(Rc::new);
     let mut diagnostics = vec![];
+
+    if string.len() > max_len {
+        let span = Span {
+            full_text: string.clone(),
+            file: file.clone(),
+            text_bytes: StartEnd { start: 0, end: 0 },
+            lines_covered: StartEnd { start: 1, end: 1 },
+            columns: StartEnd { start: 1, end: 1 },
+        };
+        diagnostics.push(ParseDiagnostic::FileTooLarge(span));
+        return Result {
+            roots: vec![],
+            source: Some(string.clone()),
+            file: file,
+            diagnostics: into_bag(diagnostics),
+        };
+    }
+
     let mut scopestack = ScopeStack::new(string.clone(), &file);
+    let is_meta_marker = |token: &TokenInfo| {
+        options.metadata && token.length == 1 && string.as_bytes()[token.byte_offset] == b'^'
+    };
+    // How many lists deep we are inside of a list that was too deep
+    let mut skipped_depth = 0;
+    // Whether the operators just before this token were too deep; a run of
+    // them is reported once
+    let mut skipped_operators = false;
+    // With `concat_strings`, a string is held back until the next token that
+    // isn't whitespace, in case that token is another string to merge with
+    let mut pending_string: Option<Sexpr> = None;
 
     loop {
         let token = match tokens.next() {
             Some(Ok(t)) => t,
-            Some(Err(e)) => {
-                diagnostics.push(ParseDiagnostic::TokenizationError(e));
+            Some(Err(mut error)) => {
+                // The tokenizer doesn't know which file it is reading
+                match error {
+                    TokError::UnclosedString(ref mut span) |
+                    TokError::AtomTooLong { ref mut span, .. } => span.file = file.clone(),
+                    TokError::Io(_) => {}
+                }
+                diagnostics.push(ParseDiagnostic::TokenizationError(error));
+                if let Some(pending) = pending_string.take() {
+                    scopestack.put(pending);
+                }
                 continue;
             }
             None => break,
         };
 
+        if skipped_depth > 0 {
+            match token.typ {
+                TokenType::ListOpening(_) => skipped_depth += 1,
+                TokenType::ListClosing(_) => skipped_depth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        if token.typ != TokenType::Whitespace && token.typ != TokenType::String {
+            if let Some(pending) = pending_string.take() {
+                scopestack.put(pending);
+            }
+        }
+
+        let opens_scope = match token.typ {
+            TokenType::ListOpening(_) | TokenType::UnaryOperator => true,
+            TokenType::Atom => is_meta_marker(&token),
+            _ => false,
+        };
+        if opens_scope && options.max_depth == Some(scopestack.depth()) {
+            let span = Span::from_token(&token, string, &file);
+            if let TokenType::ListOpening(_) = token.typ {
+                diagnostics.push(ParseDiagnostic::DepthLimitExceeded(span));
+                skipped_depth = 1;
+            } else if !skipped_operators {
+                diagnostics.push(ParseDiagnostic::DepthLimitExceeded(span));
+                skipped_operators = true;
+            }
+            continue;
+        }
+        if token.typ != TokenType::Whitespace && token.typ != TokenType::Comment {
+            skipped_operators = false;
+        }
+
         match token.typ {
+            TokenType::String if options.concat_strings => {
+                let span = Span::from_token(&token, string, &file);
+                pending_string = Some(match pending_string.take() {
+                    Some(Sexpr::String(first, ref first_span)) => {
+                        let merged = TokenInfo {
+                            length: span.text_bytes.end - first_span.text_bytes.start,
+                            ..first
+                        };
+                        Sexpr::String(merged, Span::from_spans(first_span, &span))
+                    }
+                    _ => Sexpr::String(token, span),
+                });
+            }
             TokenType::String => {
                 let span = Span::from_token(&token, string, &file);
                 scopestack.put(Sexpr::String(token, span));
             }
+            TokenType::Atom if is_meta_marker(&token) => {
+                scopestack.open_meta(token);
+            }
             TokenType::Atom => {
                 let span = Span::from_token(&token, string, &file);
                 scopestack.put(Sexpr::Terminal(token, span));
             }
-            // TODO
-            //TokenType::UnaryOperator => {
-            //    scopestack.open_unary(token);
-            //}
-            TokenType::Whitespace => { /* do nothing for now */ }
+            TokenType::UnaryOperator => {
+                scopestack.open_unary(token);
+            }
+            TokenType::Whitespace |
+            TokenType::Comment => { /* do nothing for now */ }
             TokenType::ListOpening(typ) => {
                 scopestack.open_list(typ, token);
             }
             TokenType::ListClosing(typ) => {
-                scopestack.close(Some((typ, token)), &mut diagnostics);
+                scopestack.close(Some((typ, token)), &mut diagnostics, recovery);
             }
         }
+
+        if scopestack.is_aborted() {
+            break;
+        }
     }
 
-    let out = scopestack.end(&mut diagnostics);
+    if let Some(pending) = pending_string.take() {
+        scopestack.put(pending);
+    }
+    let out = scopestack.end(&mut diagnostics, recovery);
 
     Result {
         roots: out,
-        diagnostics: diagnostics
-            .into_iter()
-            .map(ParseDiagnostic::into_diagnostic)
-            .collect(),
+        source: Some(string.clone()),
+        file: file,
+        diagnostics: into_bag(diagnostics),
     }
 }
 
+#[test]
+fn span_containment_test() {
+    let ::Result { roots, .. } = ::simple_parse("(ab (c)) d", &[], None);
+    let list = roots[0].span();
+    let ab = roots[0].iter_preorder().find(|s| s.text().as_ref() == "ab").unwrap().span();
+    let d = roots[1].span();
+
+    assert!(!list.contains_byte(8));
+    assert!(list.contains_byte(7));
+    assert!(ab.contains_byte(1) && ab.contains_byte(2) && !ab.contains_byte(3));
+
+    assert!(list.contains(ab) && list.contains(list) && !ab.contains(list));
+    assert!(!list.contains(d));
+
+    assert!(list.intersects(ab) && ab.intersects(list));
+    assert!(!list.intersects(d));
+    assert!(list.intersects(&Span::from_spans(ab, d)));
+
+    // Spans from different files never overlap
+    let other = ::simple_parse("(ab (c)) d", &[], Some("other")).roots.remove(0);
+    assert!(!list.contains(other.span()) && !list.intersects(other.span()));
+}
+
+#[test]
+fn utf16_columns_test() {
+    // The emoji is four bytes in UTF-8 but a surrogate pair in UTF-16
+    let ::Result { roots, .. } = ::simple_parse("(a)\n(\u{1F600} x)", &[], None);
+    let x = roots[1].iter_preorder().find(|s| s.text().as_ref() == "x").unwrap().span().clone();
+    assert_eq!(x.text().as_ref(), "x");
+    assert_eq!(x.columns, StartEnd { start: 7, end: 8 });
+    assert_eq!(x.utf16_columns(), StartEnd { start: 5, end: 6 });
+    assert_eq!(roots[1].span().utf16_columns(), StartEnd { start: 1, end: 7 });
+
+    // Without the source text to count, the byte columns are all there is
+    let mut detached = x.clone();
+    detached.full_text = "".into();
+    assert_eq!(detached.utf16_columns(), StartEnd { start: 7, end: 8 });
+    detached.full_text = "(a)".into();
+    assert_eq!(detached.utf16_columns(), StartEnd { start: 7, end: 8 });
+    // Offset 6 falls inside the emoji
+    let mut split = x.clone();
+    split.text_bytes = StartEnd { start: 6, end: 9 };
+    assert_eq!(split.utf16_columns(), StartEnd { start: 7, end: 8 });
+}
+
+#[test]
+fn line_col_test() {
+    let text: StrTendril = "(片仮名\n  x)\n".into();
+    let ::Result { roots, .. } = ::simple_parse(text.clone(), &[], None);
+    for sexpr in roots[0].iter_preorder() {
+        let span = sexpr.span();
+        let start = (span.lines_covered.start, span.columns.start);
+        let end = (span.lines_covered.end, span.columns.end);
+        assert_eq!(byte_to_line_col(&text, span.text_bytes.start), start);
+        assert_eq!(byte_to_line_col(&text, span.text_bytes.end), end);
+        assert_eq!(line_col_to_byte(&text, start.0, start.1), Some(span.text_bytes.start));
+        assert_eq!(line_col_to_byte(&text, end.0, end.1), Some(span.text_bytes.end));
+    }
+
+    assert_eq!(byte_to_line_col(&text, 1), (1, 2));
+    assert_eq!(byte_to_line_col(&text, 10), (1, 11));
+    assert_eq!(byte_to_line_col(&text, 11), (2, 1));
+    assert_eq!(byte_to_line_col(&text, 100), (3, 1));
+    assert_eq!(line_col_to_byte(&text, 1, 100), Some(10));
+    assert_eq!(line_col_to_byte(&text, 3, 1), Some(16));
+    assert_eq!(line_col_to_byte(&text, 4, 1), None);
+}
+
 #[test]
 fn find_newline_test() {
     let string = b"abc\n123\nxyz";
@@ -274,3 +779,357 @@ fn find_newline_test() {
         assert_eq!(&string[st..en], b"xyz");
     }
 }
+
+#[test]
+fn extra_closings_are_coalesced() {
+    let Result { roots, diagnostics, .. } = ::simple_parse("(a)))))", &[], None);
+    assert_eq!(roots.len(), 1);
+    assert_eq!(diagnostics.len(), 1);
+
+    let diagnostic = diagnostics.iter().next().unwrap();
+    assert_eq!(diagnostic.message, "4 extra closing delimiters");
+    assert_eq!(diagnostic.global_span.text().as_ref(), "))))");
+
+    let Result { diagnostics, .. } = ::simple_parse("(a)) ))", &[], None);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics.iter().next().unwrap().global_span.text().as_ref(), ") ))");
+
+    let Result { diagnostics, .. } = ::simple_parse("(a)) b )", &[], None);
+    assert_eq!(diagnostics.len(), 2);
+    assert!(diagnostics.iter().all(|d| d.message == "extra list closing"));
+}
+
+#[test]
+fn file_too_large() {
+    let source: StrTendril = "(a b c)".into();
+    let tokens = tokenize(source.clone(), &[]);
+    let Result { roots, diagnostics, .. } = parse_with_max_len(&source, tokens, None, &ParseOptions::default(), &mut DefaultRecovery, 4);
+    assert!(roots.is_empty());
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics.iter().next().unwrap().message, "file too large for u32 offsets");
+
+    let tokens = tokenize(source.clone(), &[]);
+    let Result { roots, diagnostics, .. } = parse_with_max_len(&source, tokens, None, &ParseOptions::default(), &mut DefaultRecovery, 7);
+    assert_eq!(roots.len(), 1);
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn relative_to_test() {
+    let Result { roots, .. } = ::simple_parse("(a (bc d)) e", &[], None);
+    let outer = roots[0].span();
+    let inner = match &roots[0] {
+        &Sexpr::List { ref children, .. } => children[1].span(),
+        _ => unreachable!(),
+    };
+
+    let relative = inner.relative_to(outer).unwrap();
+    assert_eq!(relative, StartEnd { start: 3, end: 9 });
+    assert_eq!(&outer.text().as_ref()[relative.start as usize..relative.end as usize],
+               inner.text().as_ref());
+
+    assert_eq!(outer.relative_to(outer), Some(StartEnd { start: 0, end: 10 }));
+    assert_eq!(roots[1].span().relative_to(outer), None);
+    assert_eq!(outer.relative_to(inner), None);
+}
+
+#[test]
+fn metadata() {
+    fn parse_meta(source: &str) -> Result {
+        let source: StrTendril = source.into();
+        let tokens = tokenize(source.clone(), &["^"]);
+        parse_with_options(&source, tokens, None, &ParseOptions::new().with_metadata(true))
+    }
+
+    let Result { roots, diagnostics, .. } = parse_meta("^{:a 1} foo bar");
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots.len(), 2);
+    match &roots[0] {
+        &Sexpr::WithMeta { ref meta, ref expr, ref span, .. } => {
+            assert_eq!(meta.text().as_ref(), "{:a 1}");
+            assert_eq!(expr.text().as_ref(), "foo");
+            assert_eq!(span.text().as_ref(), "^{:a 1} foo");
+        }
+        other => panic!("expected metadata, found {:?}", other),
+    }
+    assert_eq!(roots[1].text().as_ref(), "bar");
+
+    let Result { roots, diagnostics, .. } = parse_meta("(def ^:private x)");
+    assert!(diagnostics.is_empty());
+    match &roots[0] {
+        &Sexpr::List { ref children, .. } => assert_eq!(children[1].kind(), SexprKind::WithMeta),
+        _ => unreachable!(),
+    }
+
+    let Result { roots, diagnostics, .. } = parse_meta("(a ^:private)");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics.iter().next().unwrap().message,
+               "metadata with no following expression");
+    assert_eq!(roots[0].text().as_ref(), "(a ^:private)");
+
+    let Result { roots, diagnostics, .. } = ::simple_parse("^{:a 1} foo", &["^"], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots.len(), 3);
+}
+
+#[test]
+fn span_construction_does_not_scan_source() {
+    let source: StrTendril = "abc\ndef".into();
+    // Deliberately inconsistent position info; it should be copied, not recomputed
+    let token = TokenInfo {
+        line_number: 7,
+        column_number: 9,
+        byte_offset: 4,
+        typ: TokenType::Atom,
+        length: 3,
+    };
+    let span = Span::from_token(&token, &source, &None);
+    assert_eq!(span.text().as_ref(), "def");
+    assert_eq!(span.lines_covered, StartEnd { start: 7, end: 7 });
+    assert_eq!(span.columns, StartEnd { start: 9, end: 12 });
+    assert_eq!(span.lines().as_ref(), "def");
+}
+
+#[test]
+fn file_name_test() {
+    let Result { roots, .. } = ::simple_parse("a", &[], Some("foo.lisp"));
+    assert_eq!(roots[0].span().file_name(), Some("foo.lisp"));
+
+    let Result { roots, .. } = ::simple_parse("a", &[], None);
+    assert_eq!(roots[0].span().file_name(), None);
+}
+
+#[test]
+fn custom_recovery_aborts() {
+    struct AbortOnUnclosed {
+        seen: usize,
+    }
+
+    impl RecoveryStrategy for AbortOnUnclosed {
+        fn unclosed_list(&mut self, _span: &Span) -> Recovery {
+            self.seen += 1;
+            Recovery::Abort
+        }
+    }
+
+    let source: StrTendril = "a (b (c d".into();
+    let tokens = tokenize(source.clone(), &[]);
+    let Result { roots, diagnostics, .. } = parse(&source, tokens, None);
+    assert_eq!(roots.len(), 2);
+    assert_eq!(diagnostics.len(), 2);
+
+    let mut strategy = AbortOnUnclosed { seen: 0 };
+    let tokens = tokenize(source.clone(), &[]);
+    let Result { roots, diagnostics, .. } =
+        parse_with_recovery(&source, tokens, None, &ParseOptions::default(), &mut strategy);
+    assert_eq!(strategy.seen, 1);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics.iter().next().unwrap().message, "unclosed list");
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].text().as_ref(), "a");
+}
+
+#[test]
+fn for_token_test() {
+    let result = ::simple_parse("(a\n  bcd)", &[], Some("file"));
+    let child = &result.roots[0].expect_list(&mut DiagnosticBag::new()).unwrap()[1];
+    let token = child.first_token().clone();
+    assert_eq!(&Span::for_token(&token, &result), child.span());
+    assert_eq!(Span::for_token(&token, &result).location_string(), "file:2:3");
+}
+
+#[test]
+fn location_string_test() {
+    let Result { roots, .. } = ::simple_parse("a\n  b", &[], Some("foo.lisp"));
+    assert_eq!(roots[1].span().location_string(), "foo.lisp:2:3");
+
+    let Result { roots, .. } = ::simple_parse("a\n  b", &[], None);
+    assert_eq!(roots[1].span().location_string(), "2:3");
+}
+
+#[test]
+fn from_spans_is_well_formed() {
+    let Result { roots, .. } = ::simple_parse("(a (bc\n d) e)\n(f g)", &[], None);
+    let mut spans = vec![];
+    for root in &roots {
+        spans.push(root.span().clone());
+        spans.extend(root.descendants().map(|s| s.span().clone()));
+    }
+
+    for a in &spans {
+        for b in &spans {
+            let joined = Span::from_spans(a, b);
+            assert_eq!(joined, Span::from_spans(b, a));
+            assert!(joined.text_bytes.start <= joined.text_bytes.end);
+            assert!(joined.lines_covered.start <= joined.lines_covered.end);
+            if joined.lines_covered.start == joined.lines_covered.end {
+                assert!(joined.columns.start <= joined.columns.end);
+            }
+            assert_eq!(joined.text_bytes.start, ::std::cmp::min(a.text_bytes.start, b.text_bytes.start));
+            assert_eq!(joined.text_bytes.end, ::std::cmp::max(a.text_bytes.end, b.text_bytes.end));
+        }
+    }
+}
+
+#[test]
+fn sexpr_kind_names() {
+    assert_eq!(SexprKind::List.name(), "list");
+    assert_eq!(SexprKind::UnaryOperator.name(), "unary operator");
+    assert_eq!(SexprKind::WithMeta.name(), "expression with metadata");
+    assert_eq!(SexprKind::Terminal.name(), "terminal");
+    assert_eq!(SexprKind::String.name(), "string");
+}
+
+#[test]
+fn string_literals() {
+    let Result { roots, diagnostics, .. } = ::simple_parse(r#"(greet "hello world")"#, &[], None);
+    assert!(diagnostics.is_empty());
+    match &roots[0] {
+        &Sexpr::List { ref children, .. } => {
+            assert_eq!(children.len(), 2);
+            assert_eq!(children[1].kind(), SexprKind::String);
+            assert_eq!(children[1].text().as_ref(), r#""hello world""#);
+        }
+        _ => unreachable!(),
+    }
+
+    let Result { roots, diagnostics, .. } = ::simple_parse(r#"(greet "hello)"#, &[], Some("a.lisp"));
+    assert_eq!(roots.len(), 1);
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.message.clone()).collect();
+    assert_eq!(messages, vec!["unclosed string", "unclosed list"]);
+    assert_eq!(diagnostics.iter().next().unwrap().global_span.file_name(), Some("a.lisp"));
+}
+
+#[test]
+fn comments_are_skipped() {
+    let Result { roots, diagnostics, .. } = ::simple_parse("(foo ; comment (\n bar) ; trailing", &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots.len(), 1);
+    match &roots[0] {
+        &Sexpr::List { ref children, .. } => {
+            let texts: Vec<_> = children.iter().map(|c| c.text().to_string()).collect();
+            assert_eq!(texts, vec!["foo", "bar"]);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn unary_operators() {
+    fn parse_ops(input: &str) -> Result {
+        let source: StrTendril = input.into();
+        let options = TokenizeOptions::new().with_unary_operators(&["'", ",", ",@"]);
+        let tokens = tokenize_with_options(source.clone(), &[], options);
+        parse(&source, tokens, None)
+    }
+
+    let Result { roots, diagnostics, .. } = parse_ops("'(a ,b ,@c) ''d");
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots.len(), 2);
+    assert_eq!(roots[0].kind(), SexprKind::UnaryOperator);
+    assert_eq!(roots[0].text().as_ref(), "'(a ,b ,@c)");
+    match &roots[1] {
+        &Sexpr::UnaryOperator { ref child, ref span, .. } => {
+            assert_eq!(span.text().as_ref(), "''d");
+            assert_eq!(child.kind(), SexprKind::UnaryOperator);
+            assert_eq!(child.text().as_ref(), "'d");
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(roots[0].to_source_string(), "'(a ,b ,@c)");
+
+    let Result { roots, diagnostics, .. } = parse_ops("(a ') ,");
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.message.clone()).collect();
+    assert_eq!(messages,
+               vec!["unary operator `'` with no argument", "unary operator `,` with no argument"]);
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].text().as_ref(), "(a ')");
+}
+
+#[test]
+fn is_complete_test() {
+    match is_complete("(a", &[]) {
+        Completeness::Incomplete(1) => {}
+        other => panic!("expected incomplete, found {:?}", other),
+    }
+    match is_complete("(a [b \"c)", &[]) {
+        Completeness::Incomplete(2) => {}
+        other => panic!("expected incomplete, found {:?}", other),
+    }
+    match is_complete("(a) b", &[]) {
+        Completeness::Complete => {}
+        other => panic!("expected complete, found {:?}", other),
+    }
+    match is_complete(")", &[]) {
+        Completeness::Invalid(diagnostic) => assert_eq!(diagnostic.message, "extra list closing"),
+        other => panic!("expected invalid, found {:?}", other),
+    }
+    match is_complete("(a]", &[]) {
+        Completeness::Invalid(diagnostic) => assert_eq!(diagnostic.message, "Expected ) but found ]"),
+        other => panic!("expected invalid, found {:?}", other),
+    }
+}
+
+#[test]
+fn concat_strings_test() {
+    let input: StrTendril = "(\"hello \" \"world\" \"!\"\n \"x\" ; comment\n \"y\")".into();
+    let parse = |options: &ParseOptions| {
+        let tokens = tokenize(input.clone(), &[]);
+        parse_with_options(&input, tokens, None, options).roots
+    };
+    let strings = |roots: &[Sexpr]| {
+        roots[0].descendants().map(|s| unescape_string(s.text().as_ref())).collect::<Vec<_>>()
+    };
+
+    let separate = parse(&ParseOptions::new());
+    assert_eq!(strings(&separate), vec!["hello ", "world", "!", "x", "y"]);
+
+    let merged = parse(&ParseOptions::new().with_concat_strings(true));
+    assert_eq!(strings(&merged), vec!["hello world!x", "y"]);
+    let first = merged[0].descendants().next().unwrap();
+    assert_eq!(first.kind(), SexprKind::String);
+    assert_eq!(first.text().as_ref(), "\"hello \" \"world\" \"!\"\n \"x\"");
+    assert_eq!(first.span().lines_covered, StartEnd { start: 1, end: 2 });
+    assert_eq!(merged[0].to_string(), "(\"hello world!x\" \"y\")");
+
+    let input: StrTendril = "\"hello \" \"world\"".into();
+    let options = ParseOptions::new().with_concat_strings(true);
+    let roots = parse_with_options(&input, tokenize(input.clone(), &[]), None, &options).roots;
+    assert_eq!(roots.len(), 1);
+    assert_eq!(unescape_string(roots[0].text().as_ref()), "hello world");
+}
+
+#[test]
+fn max_depth_test() {
+    let parse_limited = |input: &str, depth: usize| {
+        let source: StrTendril = input.into();
+        let options = ParseOptions::new().with_max_depth(depth);
+        parse_with_options(&source, tokenize(source.clone(), &[]), None, &options)
+    };
+
+    let Result { roots, diagnostics, .. } = parse_limited("(a (b (c (d)) e) f) g", 2);
+    assert_eq!(roots.iter().map(Sexpr::to_source_string).collect::<Vec<_>>(), vec!["(a (b e) f)", "g"]);
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = diagnostics.iter().next().unwrap();
+    assert_eq!(diagnostic.message, "nesting exceeds the depth limit");
+    assert_eq!(diagnostic.global_span.location_string(), "1:7");
+
+    let input: String = ::std::iter::repeat('(').take(100000).collect();
+    let Result { roots, diagnostics, .. } = parse_limited(&input, 64);
+    assert_eq!(roots.len(), 1);
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert!(messages.contains(&"nesting exceeds the depth limit"));
+    assert_eq!(messages.iter().filter(|&&m| m == "unclosed list").count(), 64);
+
+    // Unary operators count toward the limit too
+    let input: String = ::std::iter::repeat('\'').take(100000).chain(Some('a')).collect();
+    let source: StrTendril = input.as_str().into();
+    let options = ParseOptions::new().with_max_depth(64);
+    let tokenize_options = TokenizeOptions::new().with_unary_operators(&["'"]);
+    let tokens = tokenize_with_options(source.clone(), &[], tokenize_options);
+    let Result { roots, diagnostics, .. } = parse_with_options(&source, tokens, None, &options);
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].iter_preorder().count(), 65);
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages, vec!["nesting exceeds the depth limit"]);
+}
@@ -0,0 +1,44 @@
+use super::Span;
+
+/// What the parser should do after recovering from a malformed list.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Recovery {
+    /// Report the diagnostic and keep parsing.
+    Continue,
+    /// Report the diagnostic and stop; lists that are still open are dropped.
+    Abort,
+}
+
+/// Hooks invoked by the parser whenever it has to recover from
+/// mismatched or missing list delimiters.
+///
+/// Every method has a default that matches the parser's normal behavior,
+/// so implementors only need to override the cases they care about.
+pub trait RecoveryStrategy {
+    /// Computes the span of a list that was never closed, given the span of
+    /// its opening token and the span of the last token inside it.
+    fn unclosed_list_span(&mut self, opening: &Span, last: &Span) -> Span {
+        Span::from_spans(opening, last)
+    }
+
+    /// Called after an unclosed list has been given its span.
+    fn unclosed_list(&mut self, _span: &Span) -> Recovery {
+        Recovery::Continue
+    }
+
+    /// Called for a closing delimiter that has no list to close.
+    fn extra_closing(&mut self, _span: &Span) -> Recovery {
+        Recovery::Continue
+    }
+
+    /// Called when a list is closed by the wrong kind of delimiter.
+    fn wrong_closing(&mut self, _opening: &Span, _closing: &Span) -> Recovery {
+        Recovery::Continue
+    }
+}
+
+/// The recovery strategy used by `parse` and `parse_with_options`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultRecovery;
+
+impl RecoveryStrategy for DefaultRecovery {}
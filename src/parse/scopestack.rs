@@ -10,12 +10,18 @@ enum ParseStackItem {
         typ: ListType,
         children: Vec<Sexpr>,
     },
+    Meta {
+        caret: TokenInfo,
+        meta: Option<Sexpr>,
+    },
+    Unary { op: TokenInfo },
 }
 
 pub struct ScopeStack {
     stack: Vec<ParseStackItem>,
     string: StrTendril,
     file: Option<Rc<String>>,
+    aborted: bool,
 }
 
 impl ScopeStack {
@@ -24,9 +30,20 @@ impl ScopeStack {
             stack: vec![ParseStackItem::Global { children: vec![] }],
             string: string,
             file: file.clone(),
+            aborted: false,
         }
     }
 
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    // The number of lists, unary operators and metadata forms that are
+    // currently open
+    pub fn depth(&self) -> usize {
+        self.stack.len() - 1
+    }
+
     pub fn open_list(&mut self, typ: ListType, token: TokenInfo) {
         self.stack
             .push(ParseStackItem::ListOpening {
@@ -36,10 +53,27 @@ impl ScopeStack {
                   });
     }
 
-    pub fn end(mut self, diagnostics: &mut Vec<ParseDiagnostic>) -> Vec<Sexpr> {
-        while self.stack.len() != 1 {
-            self.close(None, diagnostics);
+    pub fn open_meta(&mut self, caret: TokenInfo) {
+        self.stack
+            .push(ParseStackItem::Meta {
+                      caret: caret,
+                      meta: None,
+                  });
+    }
+
+    pub fn open_unary(&mut self, op: TokenInfo) {
+        self.stack.push(ParseStackItem::Unary { op: op });
+    }
+
+    pub fn end<R: RecoveryStrategy>(mut self,
+                                    diagnostics: &mut Vec<ParseDiagnostic>,
+                                    recovery: &mut R)
+                                    -> Vec<Sexpr> {
+        while self.stack.len() != 1 && !self.aborted {
+            self.close(None, diagnostics, recovery);
         }
+        // An abort drops whatever lists were still open
+        self.stack.truncate(1);
 
         let global = self.stack.pop().unwrap();
 
@@ -50,36 +84,88 @@ impl ScopeStack {
         }
     }
 
-    pub fn put(&mut self, expr: Sexpr) {
-        let recurse = match self.stack.last_mut().unwrap() {
-            &mut ParseStackItem::Global { ref mut children } => {
-                children.push(expr);
-                None
-            }
-            &mut ParseStackItem::ListOpening { ref mut children, .. } => {
-                children.push(expr);
-                None
-            }
-        };
+    pub fn put(&mut self, mut expr: Sexpr) {
+        // Completing a unary operator or metadata form puts the result into
+        // the scope below it, so this loops once per completed form
+        loop {
+            let completed = match self.stack.last_mut().unwrap() {
+                &mut ParseStackItem::Global { ref mut children } => {
+                    children.push(expr);
+                    None
+                }
+                &mut ParseStackItem::ListOpening { ref mut children, .. } => {
+                    children.push(expr);
+                    None
+                }
+                &mut ParseStackItem::Unary { ref op } => {
+                    let span = Span::from_spans(&Span::from_token(op, &self.string, &self.file),
+                                                expr.span());
+                    Some(Sexpr::UnaryOperator {
+                             op: *op,
+                             child: Box::new(expr),
+                             span: span,
+                         })
+                }
+                &mut ParseStackItem::Meta { ref caret, ref mut meta } => {
+                    match meta.take() {
+                        None => {
+                            *meta = Some(expr);
+                            None
+                        }
+                        Some(m) => {
+                            let span = Span::from_spans(&Span::from_token(caret,
+                                                                          &self.string,
+                                                                          &self.file),
+                                                        expr.span());
+                            Some(Sexpr::WithMeta {
+                                     caret: *caret,
+                                     meta: Box::new(m),
+                                     expr: Box::new(expr),
+                                     span: span,
+                                 })
+                        }
+                    }
+                }
+            };
 
-        match recurse {
-            None => {}
-            Some(expr) => {
-                self.stack.pop();
-                self.put(expr);
+            match completed {
+                None => return,
+                Some(completed) => {
+                    self.stack.pop();
+                    expr = completed;
+                }
             }
         }
     }
 
-    pub fn close(&mut self,
-                 closed_by: Option<(ListType, TokenInfo)>,
-                 diagnostics: &mut Vec<ParseDiagnostic>) {
-        match (self.stack.pop().unwrap(), closed_by.clone()) {
+    pub fn close<R: RecoveryStrategy>(&mut self,
+                                      closed_by: Option<(ListType, TokenInfo)>,
+                                      diagnostics: &mut Vec<ParseDiagnostic>,
+                                      recovery: &mut R) {
+        let item = self.stack.pop().unwrap();
+        match (item, closed_by.clone()) {
             (g @ ParseStackItem::Global { .. }, Some((_closed_by_lst_typ, closed_by_tok))) => {
                 self.stack.push(g);
-                diagnostics.push(ParseDiagnostic::ExtraClosing(Span::from_token(&closed_by_tok,
-                                                                                &self.string,
-                                                                                &self.file)));
+                let span = Span::from_token(&closed_by_tok, &self.string, &self.file);
+                self.aborted = recovery.extra_closing(&span) == Recovery::Abort;
+
+                // Extra closers separated only by whitespace are reported together
+                if let Some(&mut ParseDiagnostic::ExtraClosing { span: ref mut prev, ref mut count }) =
+                    diagnostics.last_mut() {
+                    let between = &self.string.as_bytes()[prev.text_bytes.end as usize..
+                                                          span.text_bytes.start as usize];
+                    if between.iter().all(|b| b" \n\r\t".contains(b)) {
+                        let merged = Span::from_spans(prev, &span);
+                        *prev = merged;
+                        *count += 1;
+                        return;
+                    }
+                }
+
+                diagnostics.push(ParseDiagnostic::ExtraClosing {
+                                     span: span,
+                                     count: 1,
+                                 });
             }
             (ParseStackItem::ListOpening {
                  children,
@@ -103,19 +189,15 @@ impl ScopeStack {
 
                     self.put(list_sexpr);
                 } else {
-                    let span =
-                        Span::from_spans(&Span::from_token(&opening, &self.string, &self.file),
-                                         &Span::from_token(&closed_by_tok,
-                                                           &self.string,
-                                                           &self.file));
+                    let opening_span = Span::from_token(&opening, &self.string, &self.file);
+                    let closing_span = Span::from_token(&closed_by_tok, &self.string, &self.file);
+                    let span = Span::from_spans(&opening_span, &closing_span);
+                    self.aborted = recovery.wrong_closing(&opening_span, &closing_span) ==
+                                   Recovery::Abort;
 
                     diagnostics.push(ParseDiagnostic::WrongClosing {
-                                         opening_span: Span::from_token(&opening,
-                                                                        &self.string,
-                                                                        &self.file),
-                                         closing_span: Span::from_token(&closed_by_tok,
-                                                                        &self.string,
-                                                                        &self.file),
+                                         opening_span: opening_span,
+                                         closing_span: closing_span,
                                          expected_list_type: typ,
                                          actual_list_type: closed_by_lst_typ,
                                      });
@@ -128,7 +210,32 @@ impl ScopeStack {
                         span: span,
                     };
                     self.put(list_sexpr);
-                    self.close(closed_by, diagnostics);
+                    if !self.aborted {
+                        self.close(closed_by, diagnostics, recovery);
+                    }
+                }
+            }
+            (ParseStackItem::Meta { caret, meta }, closed_by) => {
+                let caret_span = Span::from_token(&caret, &self.string, &self.file);
+                let span = match meta {
+                    Some(ref m) => Span::from_spans(&caret_span, m.span()),
+                    None => caret_span,
+                };
+                diagnostics.push(ParseDiagnostic::MetadataWithNoTarget(span));
+
+                // Keep the metadata form in the tree so it isn't lost
+                if let Some(m) = meta {
+                    self.put(m);
+                }
+                if closed_by.is_some() {
+                    self.close(closed_by, diagnostics, recovery);
+                }
+            }
+            (ParseStackItem::Unary { op }, closed_by) => {
+                let span = Span::from_token(&op, &self.string, &self.file);
+                diagnostics.push(ParseDiagnostic::UnaryOpWithNoArgument(span));
+                if closed_by.is_some() {
+                    self.close(closed_by, diagnostics, recovery);
                 }
             }
             (ParseStackItem::Global { .. }, None) => unreachable!(),
@@ -144,9 +251,13 @@ impl ScopeStack {
                     opening.clone()
                 };
 
-                let span =
-                    Span::from_spans(&Span::from_token(&opening, &self.string, &self.file),
-                                     &Span::from_token(&closed_token, &self.string, &self.file));
+                let span = recovery.unclosed_list_span(&Span::from_token(&opening,
+                                                                         &self.string,
+                                                                         &self.file),
+                                                       &Span::from_token(&closed_token,
+                                                                         &self.string,
+                                                                         &self.file));
+                self.aborted = recovery.unclosed_list(&span) == Recovery::Abort;
 
                 let list_sexpr = Sexpr::List {
                     opening_token: opening,
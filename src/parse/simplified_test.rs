@@ -17,22 +17,23 @@ enum SimpleSexpr {
 }
 
 impl<'a> From<Sexpr> for SimpleSexpr {
-    fn from(sexpr: Sexpr) -> SimpleSexpr {
+    fn from(mut sexpr: Sexpr) -> SimpleSexpr {
         match sexpr {
             Sexpr::List {
                 list_type,
-                children,
-                span,
+                ref mut children,
+                ref span,
                 ..
             } => {
                 SimpleSexpr::List {
                     opening: list_type.to_string(true),
                     closing: list_type.to_string(false),
                     entire: span.text().into(),
-                    children: children.into_iter().map(From::from).collect(),
+                    children: children.drain(..).map(From::from).collect(),
                 }
             }
             Sexpr::UnaryOperator { .. } => unimplemented!(),
+            Sexpr::WithMeta { .. } => unimplemented!(),
 
             s @ Sexpr::String(_, _) => SimpleSexpr::String(s.span().text().into()),
             s @ Sexpr::Terminal(_, _) => SimpleSexpr::Ident(s.span().text().into()),
@@ -44,7 +45,7 @@ fn parse_simple_err(string: &str, expected: Vec<SimpleSexpr>, _error: &str) {
     let string: StrTendril = string.into();
     let (roots, _diagnostics) = {
         let tokens = tokenize(string.clone(), &[]);
-        let Result { roots, diagnostics } = parse(&string, tokens, None);
+        let Result { roots, diagnostics, .. } = parse(&string, tokens, None);
         (roots, diagnostics)
     };
 
@@ -64,7 +65,7 @@ fn parse_simple_ok_split(string: &str, expected: Vec<SimpleSexpr>, splits: &[&st
     let string: StrTendril = string.into();
     let (roots, diagnostics) = {
         let tokens = tokenize(string.clone(), splits);
-        let Result { roots, diagnostics } = parse(&string, tokens, None);
+        let Result { roots, diagnostics, .. } = parse(&string, tokens, None);
         (roots, diagnostics)
     };
 
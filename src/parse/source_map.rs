@@ -0,0 +1,90 @@
+use std::ops::Range;
+
+use tendril::StrTendril;
+
+/// Precomputed line starts for a source text, for answering many position
+/// queries without rescanning the text each time.
+///
+/// Lines and columns are 1-based and columns are counted in bytes, the same
+/// as `Span::lines_covered`, `Span::columns` and `byte_to_line_col`.
+#[derive(Clone, Debug)]
+pub struct SourceMap {
+    // The byte offset at which each line starts; the first is always 0
+    line_starts: Vec<u32>,
+    len: u32,
+}
+
+impl SourceMap {
+    pub fn new(source: &StrTendril) -> SourceMap {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.as_bytes()
+                               .iter()
+                               .enumerate()
+                               .filter(|&(_, &b)| b == b'\n')
+                               .map(|(i, _)| i as u32 + 1));
+        SourceMap {
+            line_starts: line_starts,
+            len: source.len32(),
+        }
+    }
+
+    /// Returns the number of lines in the source.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the 1-based line and column of the byte at `byte`.  An offset
+    /// past the end of the source is treated as the end.
+    pub fn line_col(&self, byte: u32) -> (u32, u32) {
+        let byte = ::std::cmp::min(byte, self.len);
+        let index = match self.line_starts.binary_search(&byte) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        (index as u32 + 1, byte - self.line_starts[index] + 1)
+    }
+
+    /// Returns the byte range of a 1-based line, not including its newline.
+    ///
+    /// Panics if the source doesn't have that line.
+    pub fn line_range(&self, line: u32) -> Range<u32> {
+        assert!(line >= 1 && line as usize <= self.line_starts.len(),
+                "line {} is out of range",
+                line);
+        let start = self.line_starts[line as usize - 1];
+        let end = match self.line_starts.get(line as usize) {
+            Some(&next) => next - 1,
+            None => self.len,
+        };
+        start..end
+    }
+}
+
+#[test]
+fn matches_spans() {
+    use super::byte_to_line_col;
+
+    let result = ::simple_parse("(片仮名\n  (x y)\n\n z)\n", &[], None);
+    let source = result.source().unwrap();
+    let map = result.source_map();
+    assert_eq!(map.line_count(), 5);
+
+    for sexpr in result.roots[0].iter_preorder() {
+        let span = sexpr.span();
+        assert_eq!(map.line_col(span.text_bytes.start),
+                   (span.lines_covered.start, span.columns.start));
+        assert_eq!(map.line_col(span.text_bytes.end),
+                   (span.lines_covered.end, span.columns.end));
+        if span.lines_covered.start == span.lines_covered.end {
+            let line = map.line_range(span.lines_covered.start);
+            assert_eq!(&source[line.start as usize..line.end as usize], span.lines().as_ref());
+        }
+    }
+
+    for byte in 0..source.len32() + 2 {
+        assert_eq!(map.line_col(byte), byte_to_line_col(&source, byte));
+    }
+    assert_eq!(map.line_range(3), 19..19);
+    assert_eq!(map.line_range(4), 20..23);
+    assert_eq!(map.line_range(5), 24..24);
+}
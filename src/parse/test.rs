@@ -5,7 +5,7 @@ use super::*;
 pub fn test_ok(input: &str, expected: Vec<Sexpr>) {
     let tokens = tokenize(input.into(), &[]);
 
-    let Result { roots, diagnostics } = parse(&input.into(), tokens, None);
+    let Result { roots, diagnostics, .. } = parse(&input.into(), tokens, None);
     if !diagnostics.is_empty() {
         println!("{:?}", diagnostics);
         assert!(diagnostics.is_empty());
@@ -65,6 +65,22 @@ fn two_idents() {
                                  })]);
 }
 
+#[test]
+fn test_builders() {
+    // The same trees as `two_idents` and `parens`, built from their source
+    test_ok("foo bar".into(),
+            vec![Sexpr::terminal_for_test("foo bar", 0), Sexpr::terminal_for_test("foo bar", 4)]);
+    test_ok("()".into(), vec![Sexpr::list_for_test("()", 0, vec![])]);
+
+    let source = "(a [\"b\" ()])\n{}";
+    let inner = Sexpr::list_for_test(source,
+                                     3,
+                                     vec![Sexpr::string_for_test(source, 4),
+                                          Sexpr::list_for_test(source, 8, vec![])]);
+    let outer = Sexpr::list_for_test(source, 0, vec![Sexpr::terminal_for_test(source, 1), inner]);
+    test_ok(source, vec![outer, Sexpr::list_for_test(source, 13, vec![])]);
+}
+
 #[test]
 fn parens() {
     test_ok("()".into(),
@@ -9,3 +9,12 @@ pub fn nothing_found<S: Display>(span: &Span, expected: S) -> Diagnostic {
 pub fn multiple_values_found<S: Display>(span: &Span, expected: S) -> Diagnostic {
     diagnostic!(span, "expected {} but found multiple values", expected)
 }
+
+pub fn wrong_arity<S: Display>(span: &Span, expected: S, expected_len: usize, found_len: usize) -> Diagnostic {
+    diagnostic!(span, "expected {} with {} values but found {}", expected, expected_len, found_len)
+}
+
+pub fn unknown_field<S: Display>(span: &Span, expected: S, fields: &[&str]) -> Diagnostic {
+    let fields: Vec<String> = fields.iter().map(|f| format!("`{}`", f)).collect();
+    diagnostic!(WARN, span, "unknown field `{}` in {}, expected one of {}", span.text(), expected, fields.join(", "))
+}
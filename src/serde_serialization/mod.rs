@@ -3,13 +3,22 @@
 #[cfg(test)]
 mod test;
 mod diagnostics;
+mod ser;
+#[cfg(any(feature = "num-bigint", feature = "num-rational"))]
+pub mod num;
+
+use std::fmt;
+use std::rc::Rc;
 
 use serde;
 use serde::de::Visitor;
 use serde::de::IntoDeserializer;
 use super::Sexpr;
-use super::parse::Span;
-use super::diagnostic::{DiagnosticBag, Diagnostic};
+use super::parse::{Span, SexprKind};
+use super::token::{ListType, unescape_string};
+use super::diagnostic::{DiagnosticBag, Diagnostic, DiagnosticAnnotation};
+
+pub use self::ser::{to_sexpr, to_string};
 
 pub enum DeserializeResult<T> {
     AllGood(T),
@@ -17,6 +26,106 @@ pub enum DeserializeResult<T> {
     CouldntRecover(DiagnosticBag),
 }
 
+/// Options that change how s-expressions are mapped onto Rust values.
+#[derive(Clone, Debug, Default)]
+pub struct DeserializeOptions {
+    /// When set, sequences must be written with this kind of list (for example,
+    /// `[1 2 3]` for `ListType::Bracket`).
+    pub seq_list_type: Option<ListType>,
+    /// When true, enums may also be written as dotted pairs, like `(ok . 5)`,
+    /// where the car names the variant and the cdr is its payload.
+    pub dotted_enum_pairs: bool,
+    /// When true, deserialization stops at the first error-level diagnostic
+    /// instead of substituting a default or skipping the problem and carrying
+    /// on.  Warnings and notes, like those from `warn_unknown_fields` and
+    /// `quoted_scalars`, don't stop it.
+    pub strict: bool,
+    /// The terminal that separates a key from its value in maps and structs.
+    /// Defaults to `:`, as in `(a:1 b:2)`.
+    ///
+    /// The separator must also be passed to the parser as a splitter, so
+    /// that `a=1` is read as three terminals.
+    pub key_separator: Option<String>,
+    /// When true, maps are written as alternating keys and values with no
+    /// separator between them, like `(a 1 b 2)`.  Structs still use the key
+    /// separator.
+    pub unseparated_maps: bool,
+    /// When true, a struct entry whose key isn't one of the struct's fields
+    /// is skipped with a warning on the key, instead of failing the struct
+    /// with an error on its value.
+    pub warn_unknown_fields: bool,
+    /// When true, booleans and integers may also be written as strings, like
+    /// `"true"` or `"42"`.  Each such value produces an info-level diagnostic
+    /// noting the coercion.
+    pub quoted_scalars: bool,
+    // Expands `${NAME}` references in string values; set with `with_interpolation`
+    resolver: Option<Resolver>,
+}
+
+#[derive(Clone)]
+struct Resolver(Rc<dyn Fn(&str) -> Option<String>>);
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("Resolver")
+    }
+}
+
+impl DeserializeOptions {
+    pub fn new() -> DeserializeOptions {
+        DeserializeOptions::default()
+    }
+
+    pub fn with_seq_list_type(mut self, list_type: ListType) -> DeserializeOptions {
+        self.seq_list_type = Some(list_type);
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> DeserializeOptions {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_dotted_enum_pairs(mut self, dotted_enum_pairs: bool) -> DeserializeOptions {
+        self.dotted_enum_pairs = dotted_enum_pairs;
+        self
+    }
+
+    pub fn with_key_separator<S: Into<String>>(mut self, key_separator: S) -> DeserializeOptions {
+        self.key_separator = Some(key_separator.into());
+        self
+    }
+
+    pub fn with_unseparated_maps(mut self, unseparated_maps: bool) -> DeserializeOptions {
+        self.unseparated_maps = unseparated_maps;
+        self
+    }
+
+    pub fn with_warn_unknown_fields(mut self, warn_unknown_fields: bool) -> DeserializeOptions {
+        self.warn_unknown_fields = warn_unknown_fields;
+        self
+    }
+
+    pub fn with_quoted_scalars(mut self, quoted_scalars: bool) -> DeserializeOptions {
+        self.quoted_scalars = quoted_scalars;
+        self
+    }
+
+    fn key_separator(&self) -> &str {
+        self.key_separator.as_ref().map_or(":", |s| s.as_str())
+    }
+
+    /// Expands `${NAME}` references in string values with the value that
+    /// `resolver` returns for `NAME`.  A reference that `resolver` returns
+    /// `None` for is left as it is, and produces a diagnostic.
+    pub fn with_interpolation<F>(mut self, resolver: F) -> DeserializeOptions
+        where F: Fn(&str) -> Option<String> + 'static
+    {
+        self.resolver = Some(Resolver(Rc::new(resolver)));
+        self
+    }
+}
+
 #[derive(Debug)]
 enum DeserError {
     Custom { message: String, },
@@ -26,22 +135,63 @@ enum DeserError {
 struct SexprDeserializer<'sexpr, 'bag> {
     sexpr: &'sexpr Sexpr,
     bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
+    // The key of the map entry or struct field being deserialized, if any
+    field: Option<&'sexpr Sexpr>,
 }
 
 struct SeqDeserializer<'sexpr, 'bag> {
     sexprs: &'sexpr[Sexpr],
     bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
+    // When used as a map, the key that the next value belongs to
+    last_key: Option<&'sexpr Sexpr>,
+    // When used as a map, whether keys are followed by the key separator
+    separated: bool,
+}
+
+/// The `key:value` entries of a struct, read as a map.  Each entry is a
+/// slice of the struct's children, so stray values between them are skipped
+/// without copying anything.
+struct StructEntriesDeserializer<'sexpr, 'bag> {
+    entries: ::std::vec::IntoIter<&'sexpr [Sexpr]>,
+    bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
+    // The entry whose key was read last
+    current: Option<&'sexpr [Sexpr]>,
+}
+
+/// A sequence of `key:value` entries, read as `(key, value)` tuples.
+struct EntrySeqDeserializer<'sexpr, 'bag> {
+    entries: &'sexpr[Sexpr],
+    bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
+}
+
+/// A single `key:value` entry, read as a `(key, value)` tuple.
+struct EntryDeserializer<'sexpr, 'bag> {
+    entry: &'sexpr[Sexpr],
+    span: Span,
+    bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
+}
+
+struct EmptyDeserializer<'bag> {
+    bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
 }
 
 struct EnumDeserializer<'sexpr, 'bag> {
     sexprs: &'sexpr[Sexpr],
     bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
     index: u32,
 }
 
 struct VariantDeserializer<'sexpr, 'bag> {
     sexprs: &'sexpr[Sexpr],
     bag: &'bag mut DiagnosticBag,
+    options: &'bag DeserializeOptions,
 }
 
 impl <T> DeserializeResult<T> {
@@ -87,16 +237,118 @@ impl <'a, T> ::std::iter::FromIterator<DeserializeResult<T>> for DeserializeResu
     }
 }
 pub fn deserialize<'sexpr, T: serde::Deserialize<'sexpr>>(sexpr: &'sexpr Sexpr) -> DeserializeResult<T> {
+    deserialize_with_options(sexpr, &DeserializeOptions::default())
+}
+
+/// Deserializes a value like `deserialize`, with non-default `DeserializeOptions`.
+pub fn deserialize_with_options<'sexpr, T: serde::Deserialize<'sexpr>>(sexpr: &'sexpr Sexpr,
+                                                                        options: &DeserializeOptions)
+                                                                        -> DeserializeResult<T> {
     let mut bag = DiagnosticBag::new();
     let res = {
         let deserializer = SexprDeserializer {
             sexpr: sexpr,
             bag: &mut bag,
+            options: options,
+            field: None,
         };
 
         T::deserialize(deserializer)
     };
 
+    into_deserialize_result(res, bag)
+}
+
+/// Deserializes a value like `deserialize`, expanding `${NAME}` references in
+/// string values with `resolver`, as in `DeserializeOptions::with_interpolation`.
+pub fn deserialize_with_interpolation<'sexpr, T, F>(sexpr: &'sexpr Sexpr, resolver: F) -> DeserializeResult<T>
+    where T: serde::Deserialize<'sexpr>,
+          F: Fn(&str) -> Option<String> + 'static
+{
+    deserialize_with_options(sexpr, &DeserializeOptions::default().with_interpolation(resolver))
+}
+
+/// Deserializes a value like `deserialize`, reading `separator` between map
+/// keys and values instead of `:`, as in `DeserializeOptions::with_key_separator`.
+///
+/// The separator has to be one of the splitters the source was parsed with,
+/// so that `{a=5}` is read as three terminals.
+pub fn deserialize_with_separator<'sexpr, T: serde::Deserialize<'sexpr>>(sexpr: &'sexpr Sexpr,
+                                                                          separator: &str)
+                                                                          -> DeserializeResult<T> {
+    deserialize_with_options(sexpr, &DeserializeOptions::default().with_key_separator(separator))
+}
+
+/// Deserializes a value, failing if any diagnostic is produced.
+///
+/// Deserialization stops at the first error (see `DeserializeOptions::strict`),
+/// so no partially-constructed value is ever returned; the `Err` holds the
+/// diagnostics that caused it.
+pub fn deserialize_strict<'sexpr, T: serde::Deserialize<'sexpr>>(sexpr: &'sexpr Sexpr) -> Result<T, DiagnosticBag> {
+    match deserialize_with_options(sexpr, &DeserializeOptions::default().with_strict(true)) {
+        DeserializeResult::AllGood(t) => Ok(t),
+        DeserializeResult::CouldRecover(_, bag) |
+        DeserializeResult::CouldntRecover(bag) => Err(bag),
+    }
+}
+
+/// Deserializes a value from all of the roots of a parse.
+///
+/// When there are no roots, types that can be empty (like `Option` or `Vec`)
+/// are deserialized as `None` or empty, and a diagnostic is produced for anything else.
+/// When there is more than one root, a diagnostic is produced.
+pub fn deserialize_maybe<'sexpr, T: serde::Deserialize<'sexpr>>(roots: &'sexpr [Sexpr]) -> DeserializeResult<T> {
+    match roots.len() {
+        0 => {
+            let mut bag = DiagnosticBag::new();
+            let options = DeserializeOptions::default();
+            let res = T::deserialize(EmptyDeserializer { bag: &mut bag, options: &options });
+            into_deserialize_result(res, bag)
+        }
+        1 => deserialize(&roots[0]),
+        _ => {
+            let span: Span = roots.iter().map(Sexpr::span).collect();
+            let diagnostic = diagnostics::multiple_values_found(&span, "a single value");
+            DeserializeResult::CouldntRecover(DiagnosticBag::singleton(diagnostic))
+        }
+    }
+}
+
+/// Parses `s` and deserializes its single top-level expression, like
+/// `serde_json::from_str`.
+///
+/// Parse diagnostics are included in the result.  Input with no expressions
+/// fails, as does input with more than one; the diagnostic for the latter
+/// points at the extra expressions.
+pub fn from_str<T: serde::de::DeserializeOwned>(s: &str, file: Option<&str>) -> DeserializeResult<T> {
+    let ::Result { roots, diagnostics: mut bag, .. } = ::simple_parse(s, &[":"], file);
+    match roots.len() {
+        0 => {
+            bag.add(diagnostics::nothing_found(&Span::empty(), "a value"));
+            return DeserializeResult::CouldntRecover(bag);
+        }
+        1 => {}
+        _ => {
+            let extra: Span = roots[1..].iter().map(Sexpr::span).collect();
+            bag.add(diagnostics::multiple_values_found(&extra, "a single value"));
+            return DeserializeResult::CouldntRecover(bag);
+        }
+    }
+
+    match deserialize::<T>(&roots[0]) {
+        DeserializeResult::AllGood(t) => into_deserialize_result(Ok(t), bag),
+        DeserializeResult::CouldRecover(t, more) => {
+            bag.append(more);
+            DeserializeResult::CouldRecover(t, bag)
+        }
+        DeserializeResult::CouldntRecover(more) => {
+            bag.append(more);
+            DeserializeResult::CouldntRecover(bag)
+        }
+    }
+}
+
+fn into_deserialize_result<T>(res: Result<T, DeserError>, bag: DiagnosticBag) -> DeserializeResult<T> {
     match res {
         Ok(t) => {
             if bag.is_empty() {
@@ -152,6 +404,80 @@ fn wrap_visitor_result<T>(result: Result<T, DeserError>, span: &Span, bag: &mut
     }
 }
 
+fn is_non_finite_literal(text: &str) -> bool {
+    let text = text.trim_left_matches(|c| c == '+' || c == '-').to_lowercase();
+    text == "inf" || text == "infinity" || text == "nan"
+}
+
+fn float_overflow<F>(sexpr: &Sexpr, typ: &str, is_finite: fn(F) -> bool) -> Option<Diagnostic>
+    where F: ::std::str::FromStr + Copy
+{
+    if let &Sexpr::Terminal(_, ref span) = sexpr {
+        let text = span.text();
+        match text.as_ref().parse::<F>() {
+            Ok(x) if !is_finite(x) && !is_non_finite_literal(text.as_ref()) => {
+                Some(diagnostic!(span, "value `{}` overflows {}", text, typ))
+            }
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+// Splits an optional leading sign and `0x`/`0o`/`0b` radix prefix off of an integer literal
+fn split_integer_literal(text: &str) -> (&str, u32, &str) {
+    let (sign, rest) = if text.starts_with('-') || text.starts_with('+') {
+        text.split_at(1)
+    } else {
+        ("", text)
+    };
+
+    let radix = match rest.get(0..2) {
+        Some("0x") | Some("0X") => 16,
+        Some("0o") | Some("0O") => 8,
+        Some("0b") | Some("0B") => 2,
+        _ => return (sign, 10, rest),
+    };
+
+    let digits = &rest[2..];
+    if digits.starts_with('-') || digits.starts_with('+') {
+        // Leave it as a (malformed) decimal literal so that parsing fails
+        return (sign, 10, rest);
+    }
+    (sign, radix, digits)
+}
+
+macro_rules! integer_parser {
+    ($name: ident, $typ: ty) => {
+        fn $name(text: &str) -> Result<$typ, ::std::num::ParseIntError> {
+            let (sign, radix, digits) = split_integer_literal(text);
+            <$typ>::from_str_radix(&format!("{}{}", sign, digits), radix)
+        }
+    }
+}
+
+integer_parser!(parse_u8, u8);
+integer_parser!(parse_u16, u16);
+integer_parser!(parse_u32, u32);
+integer_parser!(parse_u64, u64);
+integer_parser!(parse_i8, i8);
+integer_parser!(parse_i16, i16);
+integer_parser!(parse_i32, i32);
+integer_parser!(parse_i64, i64);
+
+fn parse_char(s: &str) -> Result<char, ()> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(()),
+    }
+}
+
+fn parse_str(s: &str) -> Result<&str, ()> {
+    Ok(s)
+}
+
 fn add<T>(bag: &mut DiagnosticBag, diagnostic: Diagnostic) -> Result<T, DeserError> {
     bag.add(diagnostic);
     Err(DeserError::DiagnosticAdded)
@@ -159,7 +485,8 @@ fn add<T>(bag: &mut DiagnosticBag, diagnostic: Diagnostic) -> Result<T, DeserErr
 
 macro_rules! deserialize_value {
     ($this: expr, $visitor: expr, $func: ident, $typ: ty, $parser: path, $descr: expr) => {{
-        let error = |span: &Span| diagnostic!(span, "expected to parse {} but found {}", $descr, span.text());
+        let for_field = $this.for_field();
+        let error = |span: &Span| diagnostic!(span, "expected to parse {} but found {}{}", $descr, span.text(), for_field);
         if let &Sexpr::Terminal(_, ref span) = $this.sexpr {
             let text = span.text();
             let text2 = text.as_ref();
@@ -167,80 +494,235 @@ macro_rules! deserialize_value {
             match x {
                 Ok(x) => wrap_visitor_result($visitor.$func(x), span, &mut $this.bag),
                 Err(e) => {
-                    $this.bag.add(diagnostic!(span, "could not parse `{}` as a {}", span.text(), $descr));
+                    $this.bag.add(diagnostic!(span, "could not parse `{}` as a {}{}", span.text(), $descr, for_field));
+                    if $this.options.strict {
+                        return Err(DeserError::DiagnosticAdded);
+                    }
                     wrap_visitor_result($visitor.$func(Default::default()), span, &mut $this.bag)
                 }
             }
         } else {
             $this.bag.add(error($this.sexpr.span()));
+            if $this.options.strict {
+                return Err(DeserError::DiagnosticAdded);
+            }
             wrap_visitor_result($visitor.$func(Default::default()), $this.sexpr.span(), &mut $this.bag)
         }
     }
 }}
 
+// Like `deserialize_value!`, but also reads booleans and integers out of
+// strings like `"42"` when `DeserializeOptions::quoted_scalars` is set.
+macro_rules! deserialize_scalar {
+    ($this: expr, $visitor: expr, $func: ident, $typ: ty, $parser: path, $descr: expr) => {{
+        if $this.options.quoted_scalars {
+            if let &Sexpr::String(_, ref span) = $this.sexpr {
+                let contents = unescape_string(span.text().as_ref());
+                let x: Result<$typ, _> = $parser(&contents);
+                if let Ok(x) = x {
+                    let for_field = $this.for_field();
+                    $this.bag.add(diagnostic!(INFO, span, "read {} from quoted string {}{}", $descr, span.text(), for_field));
+                    return wrap_visitor_result($visitor.$func(x), span, &mut $this.bag);
+                }
+            }
+        }
+        deserialize_value!($this, $visitor, $func, $typ, $parser, $descr)
+    }}
+}
+
+impl <'sexpr, 'bag> SexprDeserializer<'sexpr, 'bag> {
+    // Names the field being deserialized, for appending to diagnostics.
+    fn for_field(&self) -> String {
+        match self.field {
+            Some(key) => format!(" for field `{}`", key.text()),
+            None => String::new(),
+        }
+    }
+
+    // Unescapes a string literal, expanding `${NAME}` references if an
+    // interpolation resolver is set.
+    fn string_contents(&mut self, span: &Span) -> Result<String, DeserError> {
+        let contents = unescape_string(span.text().as_ref());
+        let options = self.options;
+        let resolver = match options.resolver {
+            Some(Resolver(ref resolver)) => resolver,
+            None => return Ok(contents),
+        };
+
+        let mut out = String::new();
+        let mut rest = contents.as_str();
+        let mut unresolved = false;
+        while let Some(start) = rest.find("${") {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            out.push_str(&rest[..start]);
+            let name = &rest[start + 2..end];
+            match resolver(name) {
+                Some(value) => out.push_str(&value),
+                None => {
+                    self.bag.add(diagnostic!(span, "unresolved variable `{}`{}", name, self.for_field()));
+                    unresolved = true;
+                    out.push_str(&rest[start..end + 1]);
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+
+        if unresolved && options.strict {
+            return Err(DeserError::DiagnosticAdded);
+        }
+        Ok(out)
+    }
+}
+
 impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr, 'bag> {
     type Error = DeserError;
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_any<V>(mut self, visitor: V) -> Result<V::Value, Self::Error>
         where V: Visitor<'de>
     {
-        // Can this even be implemented?
-        unimplemented!();
+        // Without a schema, the shape of the expression picks the value
+        match self.sexpr {
+            &Sexpr::List { ref children, ref span, .. } => {
+                wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag, options: self.options, last_key: None, separated: true}), span, self.bag)
+            }
+            &Sexpr::String(_, ref span) => {
+                let contents = self.string_contents(span)?;
+                wrap_visitor_result(visitor.visit_string(contents), span, self.bag)
+            }
+            &Sexpr::Terminal(_, ref span) => {
+                let text = span.text();
+                let res = if let Ok(b) = text.as_ref().parse::<bool>() {
+                    visitor.visit_bool(b)
+                } else if let Ok(i) = parse_i64(text.as_ref()) {
+                    visitor.visit_i64(i)
+                } else if let Ok(f) = text.as_ref().parse::<f64>() {
+                    visitor.visit_f64(f)
+                } else {
+                    visitor.visit_str(text.as_ref())
+                };
+                wrap_visitor_result(res, span, self.bag)
+            }
+            _ => {
+                let for_field = self.for_field();
+                add(self.bag, diagnostic!(self.sexpr.span(), "expected a value, found {}{}", self.sexpr.kind().name(), for_field))
+            }
+        }
     }
 
     fn deserialize_bool<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_bool, bool, str::parse, "boolean value")
+        deserialize_scalar!(self, visitor, visit_bool, bool, str::parse, "boolean value")
     }
 
     fn deserialize_u8<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_u8, u8, str::parse, "unsigned integer (u8)")
+        deserialize_scalar!(self, visitor, visit_u8, u8, parse_u8, "unsigned integer (u8)")
     }
 
     fn deserialize_u16<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_u16, u16, str::parse, "unsigned integer (u16)")
+        deserialize_scalar!(self, visitor, visit_u16, u16, parse_u16, "unsigned integer (u16)")
     }
 
     fn deserialize_u32<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_u32, u32, str::parse, "unsigned integer (u32)")
+        deserialize_scalar!(self, visitor, visit_u32, u32, parse_u32, "unsigned integer (u32)")
     }
 
     fn deserialize_u64<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_u64, u64, str::parse, "unsigned integer (u64)")
+        deserialize_scalar!(self, visitor, visit_u64, u64, parse_u64, "unsigned integer (u64)")
     }
 
     fn deserialize_i8<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_i8, i8, str::parse, "signed integer (i8)")
+        deserialize_scalar!(self, visitor, visit_i8, i8, parse_i8, "signed integer (i8)")
     }
 
     fn deserialize_i16<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_i16, i16, str::parse, "signed integer (i16)")
+        deserialize_scalar!(self, visitor, visit_i16, i16, parse_i16, "signed integer (i16)")
     }
 
     fn deserialize_i32<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_i32, i32, str::parse, "signed integer (i32)")
+        deserialize_scalar!(self, visitor, visit_i32, i32, parse_i32, "signed integer (i32)")
     }
 
     fn deserialize_i64<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        deserialize_value!(self, visitor, visit_i64, i64, str::parse, "signed integer (i64)")
+        deserialize_scalar!(self, visitor, visit_i64, i64, parse_i64, "signed integer (i64)")
     }
 
     fn deserialize_f32<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        if let Some(diagnostic) = float_overflow(self.sexpr, "f32", f32::is_finite) {
+            self.bag.add(diagnostic);
+            if self.options.strict {
+                return Err(DeserError::DiagnosticAdded);
+            }
+            return wrap_visitor_result(visitor.visit_f32(Default::default()), self.sexpr.span(), self.bag);
+        }
         deserialize_value!(self, visitor, visit_f32, f32, str::parse, "floating point number (f32)")
     }
 
     fn deserialize_f64<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        if let Some(diagnostic) = float_overflow(self.sexpr, "f64", f64::is_finite) {
+            self.bag.add(diagnostic);
+            if self.options.strict {
+                return Err(DeserError::DiagnosticAdded);
+            }
+            return wrap_visitor_result(visitor.visit_f64(Default::default()), self.sexpr.span(), self.bag);
+        }
         deserialize_value!(self, visitor, visit_f64, f64, str::parse, "floating point number (f64)")
     }
 
-    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> { unimplemented!(); }
+    fn deserialize_char<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        // A quoted character like `":"` is read from the string's contents
+        if let &Sexpr::String(_, ref span) = self.sexpr {
+            let contents = unescape_string(span.text().as_ref());
+            return match parse_char(&contents) {
+                Ok(c) => wrap_visitor_result(visitor.visit_char(c), span, self.bag),
+                Err(()) => {
+                    let for_field = self.for_field();
+                    self.bag.add(diagnostic!(span, "could not parse {} as a character{}", span.text(), for_field));
+                    if self.options.strict {
+                        return Err(DeserError::DiagnosticAdded);
+                    }
+                    wrap_visitor_result(visitor.visit_char(Default::default()), span, self.bag)
+                }
+            };
+        }
+        deserialize_value!(self, visitor, visit_char, char, parse_char, "character")
+    }
 
-    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {unimplemented!();}
+    fn deserialize_str<V>(mut self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        if let &Sexpr::String(_, ref span) = self.sexpr {
+            let contents = self.string_contents(span)?;
+            return wrap_visitor_result(visitor.visit_string(contents), span, self.bag);
+        }
+        deserialize_value!(self, visitor, visit_str, &str, parse_str, "string")
+    }
 
-    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {unimplemented!()}
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        self.deserialize_str(visitor)
+    }
 
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {unimplemented!()}
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        self.deserialize_byte_buf(visitor)
+    }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        unimplemented!();
+        // Bytes are either the text of a terminal or string, or a list of numbers
+        match self.sexpr {
+            &Sexpr::Terminal(_, ref span) => {
+                wrap_visitor_result(visitor.visit_bytes(span.text().as_bytes()), span, self.bag)
+            }
+            &Sexpr::String(_, ref span) => {
+                let contents = unescape_string(span.text().as_ref());
+                wrap_visitor_result(visitor.visit_byte_buf(contents.into_bytes()), span, self.bag)
+            }
+            &Sexpr::List { ref children, ref span, .. } => {
+                wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag, options: self.options, last_key: None, separated: true}), span, self.bag)
+            }
+            _ => {
+                let for_field = self.for_field();
+                add(self.bag, diagnostic!(self.sexpr.span(), "expected bytes, found {}{}", self.sexpr.kind().name(), for_field))
+            }
+        }
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
@@ -248,11 +730,11 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
             if span.text().as_ref() == "nil" {
                 wrap_visitor_result(visitor.visit_none(), self.sexpr.span(), self.bag)
             } else {
-                let r = visitor.visit_some(SexprDeserializer{sexpr: self.sexpr, bag: self.bag});
+                let r = visitor.visit_some(SexprDeserializer{sexpr: self.sexpr, bag: self.bag, options: self.options, field: self.field});
                 wrap_visitor_result(r, &self.sexpr.span(), self.bag)
             }
         } else {
-            let r = visitor.visit_some(SexprDeserializer{sexpr: self.sexpr, bag: self.bag});
+            let r = visitor.visit_some(SexprDeserializer{sexpr: self.sexpr, bag: self.bag, options: self.options, field: self.field});
             wrap_visitor_result(r, &self.sexpr.span(), self.bag)
         }
     }
@@ -277,6 +759,17 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
                                   -> Result<V::Value, Self::Error>
         where V: Visitor<'de>
     {
+        // Unit structs are written `(name)`; markers like `PhantomData` may also be written `()`
+        if let &Sexpr::List{ref children, ..} = self.sexpr {
+            let is_unit = match children.len() {
+                0 => true,
+                1 => children[0].kind() == SexprKind::Terminal && children[0].text().as_ref() == name,
+                _ => false,
+            };
+            if is_unit {
+                return wrap_visitor_result(visitor.visit_unit(), self.sexpr.span(), self.bag);
+            }
+        }
         self.deserialize_tuple_struct(name, 0, visitor)
     }
     fn deserialize_newtype_struct<V>(self,
@@ -289,10 +782,28 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
-        if let &Sexpr::List{ref children, ref span, ..} = self.sexpr {
-            wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag}), &self.sexpr.span(), self.bag)
+        if let &Sexpr::List{ref children, ref span, list_type, ref opening_token, ..} = self.sexpr {
+            match self.options.seq_list_type {
+                Some(expected) if expected != list_type => {
+                    let opening = Span::from_token(opening_token, &span.full_text, &span.file);
+                    let diagnostic = diagnostic!(span, "expected a sequence delimited by `{}{}`",
+                                                 expected.to_string(true), expected.to_string(false))
+                        .add_annotation(DiagnosticAnnotation::new(
+                            format!("expected `{}` here", expected.to_string(true)), opening));
+                    self.bag.add(diagnostic);
+                    if self.options.strict {
+                        return Err(DeserError::DiagnosticAdded);
+                    }
+                }
+                _ => {}
+            }
+            // `{a:1 b:2}` is read as a sequence of `(key, value)` tuples
+            if list_type == ListType::Brace && is_key_value_list(children, self.options.key_separator()) {
+                return wrap_visitor_result(visitor.visit_seq(EntrySeqDeserializer{entries: children, bag: self.bag, options: self.options}), span, self.bag);
+            }
+            wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag, options: self.options, last_key: None, separated: true}), &self.sexpr.span(), self.bag)
         } else {
-            self.bag.add(diagnostic!(self.sexpr.span(), "expected list, found {:?}", self.sexpr.kind()));
+            self.bag.add(diagnostic!(self.sexpr.span(), "expected list, found {}", self.sexpr.kind().name()));
             return Err(DeserError::DiagnosticAdded);
         }
     }
@@ -301,9 +812,13 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
         where V: Visitor<'de>
     {
         if let &Sexpr::List{ref children, ref span, ..} = self.sexpr {
-            wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag}), &self.sexpr.span(), self.bag)
+            if children.len() != len {
+                self.bag.add(diagnostics::wrong_arity(span, "tuple", len, children.len()));
+                return Err(DeserError::DiagnosticAdded);
+            }
+            wrap_visitor_result(visitor.visit_seq(SeqDeserializer{sexprs: children, bag: self.bag, options: self.options, last_key: None, separated: true}), &self.sexpr.span(), self.bag)
         } else {
-            self.bag.add(diagnostic!(self.sexpr.span(), "expected list, found {:?}", self.sexpr.kind()));
+            self.bag.add(diagnostic!(self.sexpr.span(), "expected list, found {}", self.sexpr.kind().name()));
             return Err(DeserError::DiagnosticAdded);
         }
     }
@@ -326,7 +841,7 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
                         Err(DeserError::DiagnosticAdded)
                     } else {
                         let vr = {
-                            let seqd = SeqDeserializer{ sexprs: &children[1..], bag: self.bag};
+                            let seqd = SeqDeserializer{ sexprs: &children[1..], bag: self.bag, options: self.options, last_key: None, separated: true};
                             visitor.visit_seq(seqd)
                         };
                         wrap_visitor_result(vr, span, self.bag)
@@ -337,7 +852,7 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
                 }
             }
         } else {
-            self.bag.add(diagnostic!(&self.sexpr.span(), "expected list, found {:?}", self.sexpr.kind()));
+            self.bag.add(diagnostic!(&self.sexpr.span(), "expected list, found {}", self.sexpr.kind().name()));
             return Err(DeserError::DiagnosticAdded);
         }
     }
@@ -345,12 +860,13 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
         if let &Sexpr::List{ref children, ref span, ..} = self.sexpr {
             let vr = {
-                let map_deser = SeqDeserializer{sexprs: children, bag: self.bag};
+                let map_deser = SeqDeserializer{sexprs: children, bag: self.bag, options: self.options,
+                                                last_key: None, separated: !self.options.unseparated_maps};
                 visitor.visit_map(map_deser)
             };
             wrap_visitor_result(vr, &self.sexpr.span(), self.bag)
         } else {
-            self.bag.add(diagnostic!(self.sexpr.span(), "expected map, found `{:?}`", self.sexpr.kind()));
+            self.bag.add(diagnostic!(self.sexpr.span(), "expected map, found {}", self.sexpr.kind().name()));
             Err(DeserError::DiagnosticAdded)
         }
     }
@@ -369,35 +885,63 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
             } else {
                 let first_child = &children[0];
                 let rest_span: Span = children[1..].iter().map(Sexpr::span).collect();
-                if let &Sexpr::Terminal(_, ref span) = first_child {
-                    if span.text().as_ref() == name {
-                        wrap_visitor_result(visitor.visit_map(
-                            SeqDeserializer{sexprs: &children[1..], bag: self.bag}), &rest_span, self.bag)
-                    } else {
-                        self.bag.add(diagnostic!(
-                            first_child.span(),
-                            "Expected structure name identifier `{}`, found `{}`",
-                            name, first_child.span().text()));
-                        Err(DeserError::DiagnosticAdded)
+                // Without a leading name or any `key:value` pairs, the fields are positional
+                let is_named = first_child.span().text().as_ref() == name;
+                let separator = self.options.key_separator();
+                let has_keys = children.iter().any(|c| c.kind() == SexprKind::Terminal && c.span().text().as_ref() == separator);
+                if !is_named && !has_keys {
+                    if children.len() != fields.len() {
+                        self.bag.add(diagnostics::wrong_arity(span, struct_descr(), fields.len(), children.len()));
+                        return Err(DeserError::DiagnosticAdded);
                     }
-                } else {
+                    return wrap_visitor_result(visitor.visit_seq(
+                        SeqDeserializer{sexprs: children, bag: self.bag, options: self.options, last_key: None, separated: true}), span, self.bag);
+                }
+                if !is_named {
                     self.bag.add(diagnostic!(
                         first_child.span(),
                         "Expected structure name identifier `{}`, found `{}`",
                         name, first_child.span().text()));
-                    Err(DeserError::DiagnosticAdded)
+                    return Err(DeserError::DiagnosticAdded);
+                }
+
+                let (mut entries, strays) = split_struct_entries(&children[1..], separator);
+                for stray in &strays {
+                    self.bag.add(diagnostic!(stray.span(), "unexpected `{}` in {}, expected `key{}value` entries",
+                                             stray.text(), struct_descr(), separator));
                 }
+                if !strays.is_empty() && self.options.strict {
+                    return Err(DeserError::DiagnosticAdded);
+                }
+                // Unknown entries are dropped after warning, so serde doesn't report
+                // their values as ignored
+                if self.options.warn_unknown_fields {
+                    let bag = &mut *self.bag;
+                    entries.retain(|entry| {
+                        let is_known = fields.contains(&entry[0].span().text().as_ref());
+                        if !is_known {
+                            bag.add(diagnostics::unknown_field(entry[0].span(), struct_descr(), fields));
+                        }
+                        is_known
+                    });
+                }
+                let map_deser = StructEntriesDeserializer {
+                    entries: entries.into_iter(),
+                    bag: self.bag,
+                    options: self.options,
+                    current: None,
+                };
+                wrap_visitor_result(visitor.visit_map(map_deser), &rest_span, self.bag)
             }
         } else {
-            self.bag.add(diagnostic!(&self.sexpr.span(), "expected {}, found {:?}", struct_descr(), self.sexpr.kind()));
+            self.bag.add(diagnostic!(&self.sexpr.span(), "expected {}, found {}", struct_descr(), self.sexpr.kind().name()));
             Err(DeserError::DiagnosticAdded)
         }
     }
     fn deserialize_identifier<V>(mut self, visitor: V) -> Result<V::Value, Self::Error>
         where V: Visitor<'de>
     {
-        fn id<T>(x: T) -> Result<T, ()>{ Ok(x) }
-        deserialize_value!(self, visitor, visit_str, &str, id, "identifier")
+        deserialize_value!(self, visitor, visit_str, &str, parse_str, "identifier")
     }
 
     fn deserialize_enum<V>(self,
@@ -415,7 +959,12 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
                 let first = &children[0];
                 if let &Sexpr::Terminal(_, ref span) = first {
                     if let Some(idx) = variants.iter().position(|&c| c == span.text().as_ref()) {
-                        let res = visitor.visit_enum(EnumDeserializer{sexprs: &children[1..], bag: self.bag, index: idx as u32});
+                        let payload = if self.options.dotted_enum_pairs && is_dotted_pair(children) {
+                            &children[2..]
+                        } else {
+                            &children[1..]
+                        };
+                        let res = visitor.visit_enum(EnumDeserializer{sexprs: payload, bag: self.bag, options: self.options, index: idx as u32});
                         wrap_visitor_result(res, span, self.bag)
                     } else {
                         add(self.bag, diagnostic!(span, "{} is not a variant name for {}", span.text(), desc()))
@@ -424,8 +973,16 @@ impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for SexprDeserializer<'sexpr,
                     add(self.bag, diagnostic!(span, "expected variant name for {}, found empty list", desc()))
                 }
             }
+        } else if let &Sexpr::Terminal(_, ref span) = self.sexpr {
+            // A bare `red` is shorthand for the unit variant `(red)`
+            if let Some(idx) = variants.iter().position(|&c| c == span.text().as_ref()) {
+                let res = visitor.visit_enum(EnumDeserializer{sexprs: &[], bag: self.bag, options: self.options, index: idx as u32});
+                wrap_visitor_result(res, span, self.bag)
+            } else {
+                add(self.bag, diagnostic!(span, "{} is not a variant name for {}", span.text(), desc()))
+            }
         } else {
-            add(self.bag, diagnostic!(self.sexpr.span(), "expected {}, found {:?}", desc(), self.sexpr.kind()))
+            add(self.bag, diagnostic!(self.sexpr.span(), "expected {}, found {}", desc(), self.sexpr.kind().name()))
         }
     }
 
@@ -447,12 +1004,106 @@ impl <'sexpr, 'bag, 'de> serde::de::SeqAccess<'de> for SeqDeserializer <'sexpr,
         }
 
         let first = &self.sexprs[0];
-        let res = seed.deserialize(SexprDeserializer {sexpr: first, bag: self.bag}).map(Some);
+        let res = seed.deserialize(SexprDeserializer {sexpr: first, bag: self.bag, options: self.options, field: None}).map(Some);
         self.sexprs = &self.sexprs[1..];
-        res
+        wrap_visitor_result(res, first.span(), self.bag)
+    }
+}
+
+
+fn is_dotted_pair(children: &[Sexpr]) -> bool {
+    children.len() == 3 && match &children[1] {
+        &Sexpr::Terminal(_, ref span) => span.text().as_ref() == ".",
+        _ => false,
+    }
+}
+
+fn is_separator(sexpr: &Sexpr, separator: &str) -> bool {
+    match sexpr {
+        &Sexpr::Terminal(_, ref span) => span.text().as_ref() == separator,
+        _ => false,
     }
 }
 
+// Separates the `key:value` entries of a struct from stray values between
+// them.  Each entry is returned as a slice of `children`.
+fn split_struct_entries<'a>(children: &'a [Sexpr], separator: &str) -> (Vec<&'a [Sexpr]>, Vec<&'a Sexpr>) {
+    let mut entries = vec![];
+    let mut strays = vec![];
+    let mut rest = children;
+    while !rest.is_empty() {
+        if rest.len() > 1 && is_separator(&rest[1], separator) {
+            // A trailing key with no value is kept, so that it is reported as a missing value
+            let len = ::std::cmp::min(rest.len(), 3);
+            entries.push(&rest[..len]);
+            rest = &rest[len..];
+        } else {
+            strays.push(&rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    (entries, strays)
+}
+
+fn is_key_value_list(children: &[Sexpr], separator: &str) -> bool {
+    !children.is_empty() && children.len() % 3 == 0 &&
+        children.chunks(3).all(|entry| is_separator(&entry[1], separator))
+}
+
+impl <'sexpr, 'bag, 'de> serde::de::SeqAccess<'de> for EntrySeqDeserializer<'sexpr, 'bag> {
+    type Error = DeserError;
+
+    fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        if self.entries.len() == 0 {
+            return Ok(None);
+        }
+
+        let entry = &self.entries[..3];
+        let span: Span = entry.iter().map(Sexpr::span).collect();
+        self.entries = &self.entries[3..];
+        let res = seed.deserialize(EntryDeserializer {entry: entry, span: span.clone(), bag: self.bag, options: self.options}).map(Some);
+        wrap_visitor_result(res, &span, self.bag)
+    }
+}
+
+impl <'sexpr, 'bag, 'de> serde::Deserializer<'de> for EntryDeserializer<'sexpr, 'bag> {
+    type Error = DeserError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        visitor.visit_seq(self)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+        where V: Visitor<'de>
+    {
+        if len != 2 {
+            return add(self.bag, diagnostics::wrong_arity(&self.span, "tuple", len, 2));
+        }
+        visitor.visit_seq(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        byte_buf option unit unit_struct newtype_struct seq tuple_struct map struct
+        enum identifier ignored_any
+    }
+}
+
+impl <'sexpr, 'bag, 'de> serde::de::SeqAccess<'de> for EntryDeserializer<'sexpr, 'bag> {
+    type Error = DeserError;
+
+    fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        if self.entry.len() == 0 {
+            return Ok(None);
+        }
+
+        // Skip over the separator between the key and the value
+        let first = &self.entry[0];
+        self.entry = if self.entry.len() > 1 { &self.entry[2..] } else { &[] };
+        let res = seed.deserialize(SexprDeserializer {sexpr: first, bag: self.bag, options: self.options, field: None}).map(Some);
+        wrap_visitor_result(res, first.span(), self.bag)
+    }
+}
 
 impl <'sexpr, 'bag, 'de> serde::de::MapAccess<'de> for SeqDeserializer<'sexpr, 'bag> {
     type Error = DeserError;
@@ -463,9 +1114,18 @@ impl <'sexpr, 'bag, 'de> serde::de::MapAccess<'de> for SeqDeserializer<'sexpr, '
             return Ok(None);
         }
 
+        if !self.separated {
+            let first = &self.sexprs[0];
+            let res = seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag, options: self.options, field: None}).map(Some);
+            self.last_key = Some(first);
+            self.sexprs = &self.sexprs[1..];
+            return wrap_visitor_result(res, first.span(), self.bag);
+        }
+
+        let separator = self.options.key_separator();
         if self.sexprs.len() == 1 {
             let all_spans = self.all_spans();
-            self.bag.add(diagnostic!(&all_spans, "expected key followed by `:`"));
+            self.bag.add(diagnostic!(&all_spans, "expected key followed by `{}`", separator));
             return Err(DeserError::DiagnosticAdded);
         }
 
@@ -473,32 +1133,73 @@ impl <'sexpr, 'bag, 'de> serde::de::MapAccess<'de> for SeqDeserializer<'sexpr, '
         let colon = &self.sexprs[1];
 
         if let &Sexpr::Terminal(_, ref span) = colon {
-            if span.text().as_ref() != ":" {
-                self.bag.add(diagnostic!(span, "expected `:`, found `{}`", span.text()));
+            if span.text().as_ref() != separator {
+                self.bag.add(diagnostic!(span, "expected `{}`, found `{}`", separator, span.text()));
+                if self.options.strict {
+                    return Err(DeserError::DiagnosticAdded);
+                }
             }
         } else {
-            self.bag.add(diagnostic!(colon.span(), "expected terminal `:`, found `{:?}`", colon.kind()));
+            self.bag.add(diagnostic!(colon.span(), "expected terminal `{}`, found {}", separator, colon.kind().name()));
+            if self.options.strict {
+                return Err(DeserError::DiagnosticAdded);
+            }
         }
 
-        let res = seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag}).map(Some);
+        let res = seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag, options: self.options, field: None}).map(Some);
 
+        self.last_key = Some(first);
         self.sexprs = &self.sexprs[2..];
 
-        res
+        wrap_visitor_result(res, first.span(), self.bag)
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
     where V: serde::de::DeserializeSeed<'de> {
         if self.sexprs.len() == 0 {
-            let all_spans = self.all_spans();
-            self.bag.add(diagnostic!(&all_spans, "expected value"));
+            // Point at the dangling key, if there is one
+            let span = match self.last_key {
+                Some(key) => key.span().clone(),
+                None => self.all_spans(),
+            };
+            self.bag.add(diagnostic!(&span, "expected value"));
             return Err(DeserError::DiagnosticAdded);
         }
 
         let first = &self.sexprs[0];
-        let res = seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag});
+        let res = seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag, options: self.options, field: self.last_key});
         self.sexprs = &self.sexprs[1..];
-        res
+        wrap_visitor_result(res, first.span(), self.bag)
+    }
+}
+
+impl <'sexpr, 'bag, 'de> serde::de::MapAccess<'de> for StructEntriesDeserializer<'sexpr, 'bag> {
+    type Error = DeserError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where K: serde::de::DeserializeSeed<'de> {
+        let entry = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.current = Some(entry);
+
+        let key = &entry[0];
+        let res = seed.deserialize(SexprDeserializer{sexpr: key, bag: self.bag, options: self.options, field: None}).map(Some);
+        wrap_visitor_result(res, key.span(), self.bag)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where V: serde::de::DeserializeSeed<'de> {
+        let entry = self.current.take().expect("value requested before its key");
+        let key = &entry[0];
+        match entry.get(2) {
+            Some(value) => {
+                let res = seed.deserialize(SexprDeserializer{sexpr: value, bag: self.bag, options: self.options, field: Some(key)});
+                wrap_visitor_result(res, value.span(), self.bag)
+            }
+            None => add(self.bag, diagnostic!(key.span(), "expected value")),
+        }
     }
 }
 
@@ -509,7 +1210,7 @@ impl <'sexpr, 'bag, 'de> serde::de::EnumAccess<'de> for EnumDeserializer<'sexpr,
                 where V: serde::de::DeserializeSeed<'de>,
     {
         let idx = seed.deserialize(self.index.into_deserializer())?;
-        Ok((idx, VariantDeserializer{sexprs: self.sexprs, bag: self.bag }))
+        Ok((idx, VariantDeserializer{sexprs: self.sexprs, bag: self.bag, options: self.options }))
     }
 }
 impl<'sexpr, 'bag, 'de> serde::de::VariantAccess<'de> for VariantDeserializer<'sexpr, 'bag>{
@@ -523,7 +1224,10 @@ impl<'sexpr, 'bag, 'de> serde::de::VariantAccess<'de> for VariantDeserializer<'s
         where T: serde::de::DeserializeSeed<'de>,
     {
         // TODO: check count of sexprs
-        seed.deserialize(SexprDeserializer{sexpr: &self.sexprs[0], bag: self.bag})
+        match self.sexprs.first() {
+            Some(first) => seed.deserialize(SexprDeserializer{sexpr: first, bag: self.bag, options: self.options, field: None}),
+            None => Err(DeserError::Custom { message: "expected a value for this variant".into() }),
+        }
     }
 
     fn tuple_variant<V>(self,
@@ -531,7 +1235,7 @@ impl<'sexpr, 'bag, 'de> serde::de::VariantAccess<'de> for VariantDeserializer<'s
                       visitor: V) -> Result<V::Value, DeserError>
         where V: serde::de::Visitor<'de>,
     {
-        let map_deser = SeqDeserializer{sexprs: self.sexprs, bag: self.bag};
+        let map_deser = SeqDeserializer{sexprs: self.sexprs, bag: self.bag, options: self.options, last_key: None, separated: true};
         visitor.visit_seq(map_deser)
     }
 
@@ -540,7 +1244,49 @@ impl<'sexpr, 'bag, 'de> serde::de::VariantAccess<'de> for VariantDeserializer<'s
                        visitor: V) -> Result<V::Value, DeserError>
         where V: serde::de::Visitor<'de>,
     {
-        let map_deser = SeqDeserializer{sexprs: self.sexprs, bag: self.bag};
+        let map_deser = SeqDeserializer{sexprs: self.sexprs, bag: self.bag, options: self.options, last_key: None, separated: true};
         visitor.visit_map(map_deser)
     }
 }
+
+impl <'bag, 'de> serde::Deserializer<'de> for EmptyDeserializer<'bag> {
+    type Error = DeserError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        add(self.bag, diagnostics::nothing_found(&Span::empty(), "a value"))
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        wrap_visitor_result(visitor.visit_none(), &Span::empty(), self.bag)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        wrap_visitor_result(visitor.visit_unit(), &Span::empty(), self.bag)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        let r = visitor.visit_seq(SeqDeserializer{sexprs: &[], bag: self.bag, options: self.options, last_key: None, separated: true});
+        wrap_visitor_result(r, &Span::empty(), self.bag)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+        let r = visitor.visit_map(SeqDeserializer{sexprs: &[], bag: self.bag, options: self.options, last_key: None, separated: true});
+        wrap_visitor_result(r, &Span::empty(), self.bag)
+    }
+
+    fn deserialize_struct<V>(self,
+                             name: &'static str,
+                             fields: &'static [&'static str],
+                             visitor: V)
+                             -> Result<V::Value, Self::Error>
+        where V: Visitor<'de>
+    {
+        add(self.bag, diagnostics::nothing_found(&Span::empty(), format!("struct {}", name)))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        byte_buf unit_struct newtype_struct tuple tuple_struct enum
+        identifier ignored_any
+    }
+}
@@ -0,0 +1,33 @@
+//! Deserializers for arbitrary-precision numbers, for use with
+//! `#[serde(deserialize_with = "...")]`.
+//!
+//! The `num-bigint` and `num-rational` crates' own `Deserialize`
+//! implementations expect their internal representation rather than text,
+//! so these read the terminal's text and parse it instead.
+
+/// Deserializes a `BigInt` from an integer literal of any size.
+#[cfg(feature = "num-bigint")]
+pub mod bigint {
+    use num_bigint::BigInt;
+    use serde::{Deserialize, Deserializer};
+    use serde::de::Error;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(|_| D::Error::custom(format!("could not parse `{}` as a big integer", text)))
+    }
+}
+
+/// Deserializes a `BigRational` from a literal like `3/4`, or from an integer
+/// literal of any size.
+#[cfg(feature = "num-rational")]
+pub mod rational {
+    use num_rational::BigRational;
+    use serde::{Deserialize, Deserializer};
+    use serde::de::Error;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigRational, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(|_| D::Error::custom(format!("could not parse `{}` as a rational", text)))
+    }
+}
@@ -0,0 +1,359 @@
+use serde;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+                 SerializeTuple, SerializeTupleStruct, SerializeTupleVariant};
+use super::super::Sexpr;
+use super::super::token::quote_string;
+
+/// Serializes a value into an s-expression tree.
+///
+/// The tree is built by parsing the output of `to_string`, so every node has a
+/// real span into that text.
+///
+/// Panics if `value`'s `Serialize` implementation fails.
+pub fn to_sexpr<T: Serialize>(value: &T) -> Sexpr {
+    let ::Result { mut roots, .. } = ::simple_parse(to_string(value), &[":"], None);
+    roots.remove(0)
+}
+
+/// Serializes a value into s-expression source text, mirroring the
+/// conventions that `deserialize` reads:
+///
+/// * structs become `(name field: value ...)`
+/// * maps become `(key: value ...)`
+/// * sequences and tuples become `(a b c)`
+/// * enum variants become `(variant ...)`
+/// * `None` becomes `nil`, and `Some(x)` is written as just `x`
+///
+/// Panics if `value`'s `Serialize` implementation fails.
+pub fn to_string<T: Serialize>(value: &T) -> String {
+    match value.serialize(Serializer) {
+        Ok(text) => text,
+        Err(SerError { message }) => panic!("failed to serialize value: {}", message),
+    }
+}
+
+#[derive(Debug)]
+struct SerError {
+    message: String,
+}
+
+impl serde::ser::Error for SerError {
+    fn custom<T: ::std::fmt::Display>(msg: T) -> Self {
+        SerError { message: format!("{}", msg) }
+    }
+}
+
+impl ::std::error::Error for SerError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ::std::fmt::Display for SerError {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(formatter, "{}", self.message)
+    }
+}
+
+struct Serializer;
+
+/// Collects the already-serialized items of a list, then wraps them in parens.
+struct ListSerializer {
+    items: Vec<String>,
+}
+
+impl ListSerializer {
+    fn new(head: Option<&str>) -> ListSerializer {
+        ListSerializer { items: head.into_iter().map(String::from).collect() }
+    }
+
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        let item = value.serialize(Serializer)?;
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn push_key(&mut self, key: &str) {
+        self.items.push(format!("{}:", key));
+    }
+
+    fn finish(self) -> Result<String, SerError> {
+        Ok(format!("({})", self.items.join(" ")))
+    }
+}
+
+// Strings are written as bare terminals when that reads back as the same text,
+// and as quoted string literals otherwise.  A bare `nil` would read back as
+// `None`, so it is quoted too.
+fn is_bare_terminal(s: &str) -> bool {
+    !s.is_empty() && s != "nil" &&
+    !s.chars().any(|c| c.is_whitespace() || "()[]{}\":;'^".contains(c))
+}
+
+impl serde::Serializer for Serializer {
+    type Ok = String;
+    type Error = SerError;
+
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = ListSerializer;
+    type SerializeMap = ListSerializer;
+    type SerializeStruct = ListSerializer;
+    type SerializeStructVariant = ListSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<String, SerError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, SerError> {
+        let mut buf = [0; 4];
+        self.serialize_str(v.encode_utf8(&mut buf))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, SerError> {
+        if is_bare_terminal(v) {
+            Ok(v.to_string())
+        } else {
+            Ok(quote_string(v))
+        }
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<String, SerError> {
+        let mut list = ListSerializer::new(None);
+        for byte in v {
+            list.push(byte)?;
+        }
+        list.finish()
+    }
+
+    fn serialize_none(self) -> Result<String, SerError> {
+        Ok("nil".to_string())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<String, SerError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, SerError> {
+        Ok("()".to_string())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, SerError> {
+        ListSerializer::new(Some(name)).finish()
+    }
+
+    fn serialize_unit_variant(self,
+                              name: &'static str,
+                              variant_index: u32,
+                              variant: &'static str)
+                              -> Result<String, SerError> {
+        ListSerializer::new(Some(variant)).finish()
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self,
+                                                       name: &'static str,
+                                                       value: &T)
+                                                       -> Result<String, SerError> {
+        let mut list = ListSerializer::new(Some(name));
+        list.push(value)?;
+        list.finish()
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self,
+                                                        name: &'static str,
+                                                        variant_index: u32,
+                                                        variant: &'static str,
+                                                        value: &T)
+                                                        -> Result<String, SerError> {
+        let mut list = ListSerializer::new(Some(variant));
+        list.push(value)?;
+        list.finish()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(None))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(None))
+    }
+
+    fn serialize_tuple_struct(self,
+                              name: &'static str,
+                              len: usize)
+                              -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(Some(name)))
+    }
+
+    fn serialize_tuple_variant(self,
+                               name: &'static str,
+                               variant_index: u32,
+                               variant: &'static str,
+                               len: usize)
+                               -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(None))
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(Some(name)))
+    }
+
+    fn serialize_struct_variant(self,
+                                name: &'static str,
+                                variant_index: u32,
+                                variant: &'static str,
+                                len: usize)
+                                -> Result<ListSerializer, SerError> {
+        Ok(ListSerializer::new(Some(variant)))
+    }
+}
+
+impl SerializeSeq for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeTuple for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleStruct for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleVariant for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeMap for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), SerError> {
+        let key = key.serialize(Serializer)?;
+        self.push_key(&key);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeStruct for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self,
+                                              key: &'static str,
+                                              value: &T)
+                                              -> Result<(), SerError> {
+        self.push_key(key);
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
+
+impl SerializeStructVariant for ListSerializer {
+    type Ok = String;
+    type Error = SerError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self,
+                                              key: &'static str,
+                                              value: &T)
+                                              -> Result<(), SerError> {
+        self.push_key(key);
+        self.push(value)
+    }
+
+    fn end(self) -> Result<String, SerError> {
+        self.finish()
+    }
+}
@@ -16,7 +16,7 @@ fn run_test_good<T: ::std::fmt::Debug + Eq + for <'a> Deserialize<'a>>(input: &s
             _ => {}
         }
     }
-    let ParseResult { roots, diagnostics }= ::simple_parse(input, &[":"], Some("run_test"));
+    let ParseResult { roots, diagnostics, .. }= ::simple_parse(input, &[":"], Some("run_test"));
     assert!(roots.len() == 1);
     diagnostics.assert_no_errors();
 
@@ -39,7 +39,7 @@ fn run_test_good<T: ::std::fmt::Debug + Eq + for <'a> Deserialize<'a>>(input: &s
 }
 
 fn run_test_bad<T: ::std::fmt::Debug + Eq + for <'a> Deserialize<'a>>(input: &str, diagnostic_messages: &[&str]) {
-    let ParseResult { roots, diagnostics }= ::simple_parse(input, &[":"], Some("run_test"));
+    let ParseResult { roots, diagnostics, .. }= ::simple_parse(input, &[":"], Some("run_test"));
     diagnostics.assert_no_errors();
     assert!(roots.len() == 1);
 
@@ -76,6 +76,43 @@ fn test_simple_deserialization() {
     run_test_bad::<u16>("-50", &["could not parse `-50` as a unsigned integer (u16)"]);
 }
 
+#[test]
+fn test_radix_integers() {
+    run_test_good("0xFF", 255 as u32);
+    run_test_good("0o17", 15 as u8);
+    run_test_good("+0b10", 2 as i32);
+    run_test_good("-0xFF", -255 as i32);
+    run_test_good("-0b101", -5 as i64);
+    run_test_good("-0", 0 as i32);
+    run_test_bad::<u32>("-0xFF", &["could not parse `-0xFF` as a unsigned integer (u32)"]);
+    run_test_bad::<i32>("0x", &["could not parse `0x` as a signed integer (i32)"]);
+    run_test_bad::<i32>("-+0x5", &["could not parse `-+0x5` as a signed integer (i32)"]);
+    run_test_bad::<i32>("0x-5", &["could not parse `0x-5` as a signed integer (i32)"]);
+}
+
+#[test]
+fn test_float_overflow() {
+    fn messages<T: for <'a> Deserialize<'a>>(input: &str) -> Vec<String> {
+        let ParseResult { roots, diagnostics, .. } = ::simple_parse(input, &[":"], Some("run_test"));
+        diagnostics.assert_no_errors();
+        match deserialize::<T>(&roots[0]) {
+            DeserializeResult::AllGood(_) => vec![],
+            DeserializeResult::CouldRecover(_, diagnostics) |
+            DeserializeResult::CouldntRecover(diagnostics) => {
+                diagnostics.iter().map(|d| d.message.clone()).collect()
+            }
+        }
+    }
+
+    assert_eq!(messages::<f32>("1e400"), vec!["value `1e400` overflows f32"]);
+    assert_eq!(messages::<f64>("1e400"), vec!["value `1e400` overflows f64"]);
+    assert_eq!(messages::<f32>("1e40"), vec!["value `1e40` overflows f32"]);
+    assert!(messages::<f64>("1e40").is_empty());
+    assert!(messages::<f32>("inf").is_empty());
+    assert!(messages::<f64>("-inf").is_empty());
+    assert!(messages::<f64>("NaN").is_empty());
+}
+
 #[test]
 fn test_seq_deserialization() {
     run_test_good("(true false true)", vec![true, false, true]);
@@ -87,7 +124,10 @@ fn test_seq_deserialization() {
 #[test]
 fn test_map_deserialization() {
     use std::collections::HashMap;
-    run_test_good::<HashMap<_,_>>("{1:true 2:false 3:true}", vec![(1, true), (2, false), (3, true)].into_iter().collect())
+    run_test_good::<HashMap<_,_>>("{1:true 2:false 3:true}", vec![(1, true), (2, false), (3, true)].into_iter().collect());
+    run_test_good::<HashMap<_,_>>("{a:1 b:2}", vec![('a', 1), ('b', 2)].into_iter().collect());
+    run_test_good::<HashMap<_,_>>("{true:1 false:2}", vec![(true, 1), (false, 2)].into_iter().collect());
+    run_test_bad::<HashMap<char, i32>>("{ab:1}", &["could not parse `ab` as a character"]);
 }
 
 #[test]
@@ -179,3 +219,787 @@ fn test_enum() {
     run_test_good("(tuple-enum 5 true)", Foo::TupleEnum(5, true));
     //run_test_good("(struct-enum x:5 b:true)", Foo::StructEnum{x: 5, b: true});
 }
+
+#[test]
+fn test_deserialize_empty_input() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo")]
+    struct Foo {
+        a: i32,
+    }
+
+    let ParseResult { roots, diagnostics, .. } = ::simple_parse("", &[":"], Some("run_test"));
+    diagnostics.assert_empty();
+
+    match deserialize_maybe::<Vec<i32>>(&roots) {
+        DeserializeResult::AllGood(v) => assert!(v.is_empty()),
+        _ => panic!("expected an empty vec"),
+    }
+
+    match deserialize_maybe::<Option<i32>>(&roots) {
+        DeserializeResult::AllGood(v) => assert_eq!(v, None),
+        _ => panic!("expected None"),
+    }
+
+    match deserialize_maybe::<Foo>(&roots) {
+        DeserializeResult::CouldntRecover(diagnostics) => {
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics.iter().next().unwrap().message,
+                       "expected struct foo but found no values");
+        }
+        _ => panic!("expected to fail"),
+    }
+}
+
+#[test]
+fn test_string_parsed_type() {
+    use std::fmt;
+
+    #[derive(Eq, PartialEq, Debug)]
+    struct Date {
+        year: u32,
+        month: u32,
+        day: u32,
+    }
+
+    impl<'de> Deserialize<'de> for Date {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Date, D::Error> {
+            struct DateVisitor;
+            impl<'de> de::Visitor<'de> for DateVisitor {
+                type Value = Date;
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a date")
+                }
+                fn visit_str<E: de::Error>(self, s: &str) -> Result<Date, E> {
+                    let parts: Vec<_> = s.splitn(2, 'T').next().unwrap().split('-').collect();
+                    let parsed: Vec<u32> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+                    match &parsed[..] {
+                        &[year, month, day] if parts.len() == 3 && month >= 1 && month <= 12 &&
+                                               day >= 1 && day <= 31 => {
+                            Ok(Date { year: year, month: month, day: day })
+                        }
+                        _ => Err(E::custom(format!("invalid date `{}`", s))),
+                    }
+                }
+            }
+            deserializer.deserialize_str(DateVisitor)
+        }
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="entry")]
+    struct Entry {
+        created: Date,
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="stamp")]
+    struct Stamp(Date);
+
+    let ParseResult { roots, diagnostics, .. } = ::simple_parse("2024-01-01T00:00:00Z", &[], None);
+    diagnostics.assert_empty();
+    assert_eq!(deserialize::<Date>(&roots[0]).unwrap(), Date { year: 2024, month: 1, day: 1 });
+
+    run_test_good("(entry created:2024-01-01)",
+                  Entry { created: Date { year: 2024, month: 1, day: 1 } });
+    run_test_good("(stamp 2024-02-03)", Stamp(Date { year: 2024, month: 2, day: 3 }));
+
+    let ParseResult { roots, diagnostics, .. } = ::simple_parse("(entry created:2024-13-01)", &[":"], None);
+    diagnostics.assert_empty();
+    match deserialize::<Entry>(&roots[0]) {
+        DeserializeResult::CouldntRecover(diagnostics) => {
+            assert_eq!(diagnostics.len(), 1);
+            let diagnostic = diagnostics.iter().next().unwrap();
+            assert_eq!(diagnostic.message, "invalid date `2024-13-01`");
+            assert_eq!(diagnostic.global_span.text().as_ref(), "2024-13-01");
+        }
+        _ => panic!("expected to fail"),
+    }
+}
+
+#[test]
+fn test_deserialize_with_validation() {
+    fn port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let port = u32::deserialize(deserializer)?;
+        if port >= 1 && port <= 65535 {
+            Ok(port)
+        } else {
+            Err(de::Error::custom("port out of range"))
+        }
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="server")]
+    struct Server {
+        #[serde(deserialize_with="port")]
+        port: u32,
+        verbose: bool,
+    }
+
+    run_test_good("(server port:8080 verbose:true)", Server { port: 8080, verbose: true });
+
+    let ParseResult { roots, diagnostics, .. } =
+        ::simple_parse("(server port:70000 verbose:true)", &[":"], None);
+    diagnostics.assert_empty();
+    match deserialize::<Server>(&roots[0]) {
+        DeserializeResult::CouldntRecover(diagnostics) => {
+            assert_eq!(diagnostics.len(), 1);
+            let diagnostic = diagnostics.iter().next().unwrap();
+            assert_eq!(diagnostic.message, "port out of range");
+            assert_eq!(diagnostic.global_span.text().as_ref(), "70000");
+        }
+        _ => panic!("expected to fail"),
+    }
+}
+
+#[test]
+fn test_rename_conventions() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="camelCase")]
+    struct Camel {
+        my_field: i32,
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="snake_case")]
+    struct Snake {
+        my_field: i32,
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="SCREAMING_SNAKE_CASE")]
+    struct Screaming {
+        my_field: i32,
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="PascalCase")]
+    struct Pascal {
+        my_field: i32,
+    }
+
+    run_test_good("(foo myField:1)", Camel { my_field: 1 });
+    run_test_good("(foo my_field:1)", Snake { my_field: 1 });
+    run_test_good("(foo MY_FIELD:1)", Screaming { my_field: 1 });
+    run_test_good("(foo MyField:1)", Pascal { my_field: 1 });
+}
+
+#[test]
+fn test_seq_list_type() {
+    use token::ListType;
+
+    let options = DeserializeOptions::new().with_seq_list_type(ListType::Bracket);
+
+    let ParseResult { roots, diagnostics, .. } = ::simple_parse("[1 2 3]", &[":"], None);
+    diagnostics.assert_empty();
+    assert_eq!(deserialize_with_options::<Vec<i32>>(&roots[0], &options).unwrap(), vec![1, 2, 3]);
+
+    let ParseResult { roots, diagnostics, .. } = ::simple_parse("(1 2 3)", &[":"], None);
+    diagnostics.assert_empty();
+    match deserialize_with_options::<Vec<i32>>(&roots[0], &options) {
+        DeserializeResult::CouldRecover(v, diagnostics) => {
+            assert_eq!(v, vec![1, 2, 3]);
+            assert_eq!(diagnostics.len(), 1);
+            let diagnostic = diagnostics.iter().next().unwrap();
+            assert_eq!(diagnostic.message, "expected a sequence delimited by `[]`");
+            assert_eq!(diagnostic.annotations.len(), 1);
+            assert_eq!(diagnostic.annotations[0].message, "expected `[` here");
+            assert_eq!(diagnostic.annotations[0].span.text().as_ref(), "(");
+            assert_eq!(diagnostic.annotations[0].span.text_bytes.start, 0);
+        }
+        _ => panic!("expected to recover"),
+    }
+
+    // Without the option, any list type is accepted
+    run_test_good("(1 2 3)", vec![1, 2, 3]);
+}
+
+#[test]
+fn test_phantom_data() {
+    use std::marker::PhantomData;
+
+    // Like other formats, serde only fills in a missing marker field if it is `default`
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo")]
+    struct Foo {
+        real: i32,
+        #[serde(default)]
+        phantom: PhantomData<String>,
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="bar")]
+    struct Bar {
+        real: i32,
+        phantom: PhantomData<String>,
+    }
+
+    run_test_good("(foo real:1)", Foo { real: 1, phantom: PhantomData });
+    run_test_good("(foo real:1 phantom:())", Foo { real: 1, phantom: PhantomData });
+    run_test_good("(bar real:1 phantom:())", Bar { real: 1, phantom: PhantomData });
+    run_test_good("(bar real:1 phantom:(PhantomData))", Bar { real: 1, phantom: PhantomData });
+}
+
+#[test]
+fn test_unit_struct() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="baz")]
+    struct Baz;
+
+    run_test_good("(baz)", Baz);
+    run_test_good("()", Baz);
+}
+
+#[test]
+fn test_deserialize_strict() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="server")]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse("(server host:localhost port:8080)", &[":"], None);
+    assert_eq!(deserialize_strict::<Server>(&roots[0]).unwrap(),
+               Server { host: "localhost".into(), port: 8080 });
+
+    // Both fields are bad, but only the first is reported
+    let ParseResult { roots, .. } = ::simple_parse("(server host:(localhost) port:http)", &[":"], None);
+    match deserialize_strict::<Server>(&roots[0]) {
+        Ok(_) => panic!("expected to fail"),
+        Err(bag) => assert_eq!(bag.len(), 1),
+    }
+
+    // A wrong key separator stops deserialization too
+    let options = DeserializeOptions::new().with_strict(true);
+    let ParseResult { roots, .. } = ::simple_parse("(a = 1 b : 2)", &[":", "="], None);
+    match deserialize_with_options::<::std::collections::BTreeMap<String, i32>>(&roots[0], &options) {
+        DeserializeResult::CouldntRecover(bag) => {
+            assert_eq!(bag.len(), 1);
+            assert_eq!(bag.iter().next().unwrap().message, "expected `:`, found `=`");
+        }
+        _ => panic!("expected to fail"),
+    }
+}
+
+#[test]
+fn test_duration() {
+    use std::time::Duration;
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="timeout")]
+    struct Timeout {
+        after: Duration,
+    }
+
+    run_test_good("(timeout after:(5 500000000))",
+                  Timeout { after: Duration::new(5, 500000000) });
+
+    let ParseResult { roots, .. } = ::simple_parse("(timeout after:(5))", &[":"], None);
+    let bag = deserialize_strict::<Timeout>(&roots[0]).unwrap_err();
+    assert_eq!(bag.iter().next().unwrap().message,
+               "expected struct Duration with 2 values but found 1");
+
+    let ParseResult { roots, .. } = ::simple_parse("(1 2 3)", &[], None);
+    let bag = deserialize_strict::<(i32, i32)>(&roots[0]).unwrap_err();
+    assert_eq!(bag.iter().next().unwrap().message, "expected tuple with 2 values but found 3");
+}
+
+#[test]
+fn test_key_value_seq() {
+    run_test_good("{a:1 b:2}", vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    // Other list types are still plain sequences
+    run_test_good("(a b)", vec!["a".to_string(), "b".to_string()]);
+
+    let ParseResult { roots, .. } = ::simple_parse("{a:1 b:2}", &[":"], None);
+    let bag = deserialize_strict::<Vec<(String, i32, i32)>>(&roots[0]).unwrap_err();
+    assert_eq!(bag.iter().next().unwrap().message, "expected tuple with 3 values but found 2");
+}
+
+#[test]
+fn test_key_separator() {
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="point")]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    let options = DeserializeOptions::new().with_key_separator("=");
+    let ParseResult { roots, .. } = ::simple_parse("{a=1 b=2} (point x=1 y=2) {a:1}", &["="], None);
+
+    let mut expected = BTreeMap::new();
+    expected.insert("a".to_string(), 1);
+    expected.insert("b".to_string(), 2);
+    assert_eq!(deserialize_with_options::<BTreeMap<String, i32>>(&roots[0], &options).unwrap(), expected);
+    assert_eq!(deserialize_with_options::<Vec<(String, i32)>>(&roots[0], &options).unwrap(),
+               vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    assert_eq!(deserialize_with_options::<Point>(&roots[1], &options).unwrap(), Point { x: 1, y: 2 });
+
+    let options = options.with_strict(true);
+    match deserialize_with_options::<BTreeMap<String, i32>>(&roots[2], &options) {
+        DeserializeResult::AllGood(_) => panic!("expected `:` to be rejected"),
+        DeserializeResult::CouldRecover(_, bag) |
+        DeserializeResult::CouldntRecover(bag) => {
+            assert_eq!(bag.iter().next().unwrap().message, "expected key followed by `=`");
+        }
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse("{a = 5} {a : 5}", &["=", ":"], None);
+    let mut expected = BTreeMap::new();
+    expected.insert("a".to_string(), 5);
+    assert_eq!(deserialize_with_separator::<BTreeMap<String, i32>>(&roots[0], "=").unwrap(), expected);
+    match deserialize_with_separator::<BTreeMap<String, i32>>(&roots[1], "=") {
+        DeserializeResult::CouldRecover(_, bag) => {
+            assert_eq!(bag.iter().next().unwrap().message, "expected `=`, found `:`");
+        }
+        _ => panic!("expected `:` to be reported"),
+    }
+}
+
+#[test]
+fn test_unseparated_maps() {
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="point")]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    let options = DeserializeOptions::new().with_unseparated_maps(true);
+    let ParseResult { roots, .. } = ::simple_parse("(a 1 b 2) (point x:1 y:2) (a 1 b)", &[":"], None);
+
+    let mut expected = BTreeMap::new();
+    expected.insert("a".to_string(), 1);
+    expected.insert("b".to_string(), 2);
+    assert_eq!(deserialize_with_options::<BTreeMap<String, i32>>(&roots[0], &options).unwrap(), expected);
+    // Structs still need the separator
+    assert_eq!(deserialize_with_options::<Point>(&roots[1], &options).unwrap(), Point { x: 1, y: 2 });
+
+    match deserialize_with_options::<BTreeMap<String, i32>>(&roots[2], &options) {
+        DeserializeResult::CouldRecover(_, bag) |
+        DeserializeResult::CouldntRecover(bag) => {
+            let diagnostic = bag.iter().next().unwrap();
+            assert_eq!(diagnostic.message, "expected value");
+            assert_eq!(diagnostic.global_span.text().as_ref(), "b");
+        }
+        DeserializeResult::AllGood(_) => panic!("expected the dangling key to be reported"),
+    }
+}
+
+#[test]
+fn test_warn_unknown_fields() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="config")]
+    struct Config {
+        #[serde(rename="my-integer", default)]
+        my_integer: i32,
+        name: String,
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse("(config my-intger:5 name:\"x\")", &[":"], None);
+    let expected = Config { my_integer: 0, name: "x".to_string() };
+    // By default the value is reported as an error, and the struct is lost
+    match deserialize::<Config>(&roots[0]) {
+        DeserializeResult::CouldntRecover(bag) => {
+            assert_eq!(bag.iter().next().unwrap().message, "ignored value");
+        }
+        _ => panic!("expected the value to be reported"),
+    }
+
+    let options = DeserializeOptions::new().with_warn_unknown_fields(true);
+    match deserialize_with_options::<Config>(&roots[0], &options) {
+        DeserializeResult::CouldRecover(value, bag) => {
+            assert_eq!(value, expected);
+            assert_eq!(bag.len(), 1);
+            let diagnostic = bag.iter().next().unwrap();
+            assert!(diagnostic.error_level == ::diagnostic::DiagnosticLevel::Warn);
+            assert_eq!(diagnostic.global_span.text().as_ref(), "my-intger");
+            assert_eq!(diagnostic.message,
+                       "unknown field `my-intger` in struct config, expected one of `my-integer`, `name`");
+        }
+        _ => panic!("expected a warning"),
+    }
+}
+
+#[test]
+fn test_dotted_enum_pairs() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename_all="kebab-case")]
+    enum Outcome {
+        Ok(i32),
+        Err(String),
+    }
+
+    let options = DeserializeOptions::new().with_dotted_enum_pairs(true);
+    let ParseResult { roots, .. } = ::simple_parse("(ok . 5) (err . boom) (ok 6)", &[], None);
+    assert_eq!(deserialize_with_options::<Outcome>(&roots[0], &options).unwrap(), Outcome::Ok(5));
+    assert_eq!(deserialize_with_options::<Outcome>(&roots[1], &options).unwrap(),
+               Outcome::Err("boom".into()));
+    // The list form still works with the option on
+    assert_eq!(deserialize_with_options::<Outcome>(&roots[2], &options).unwrap(), Outcome::Ok(6));
+
+    // Without the option, the `.` is read as the payload
+    assert!(deserialize_strict::<Outcome>(&roots[0]).is_err());
+}
+
+#[test]
+fn test_field_name_in_diagnostics() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="kebab-case")]
+    struct Foo {
+        my_integer: i32,
+        is_good: bool,
+    }
+
+    run_test_bad::<Foo>("(foo my-integer:abc is-good:true)",
+                        &["could not parse `abc` as a signed integer (i32) for field `my-integer`"]);
+    run_test_bad::<Foo>("(foo my-integer:5 is-good:(true))",
+                        &["expected to parse boolean value but found (true) for field `is-good`"]);
+
+    // Values outside of a map aren't attributed to a field
+    run_test_bad::<i32>("abc", &["could not parse `abc` as a signed integer (i32)"]);
+}
+
+#[test]
+fn test_nested_seq_deserialization() {
+    run_test_good("((1 2) (3 4))", vec![vec![1, 2], vec![3, 4]]);
+    run_test_good("((1) (2 3))", vec![vec![1], vec![2, 3]]);
+    run_test_good::<Vec<Vec<i32>>>("(() (1))", vec![vec![], vec![1]]);
+
+    let ParseResult { roots, .. } = ::simple_parse("((1 2)\n (3 x))", &[], None);
+    match deserialize::<Vec<Vec<i32>>>(&roots[0]) {
+        DeserializeResult::CouldRecover(_, diagnostics) => {
+            assert_eq!(diagnostics.len(), 1);
+            let diagnostic = diagnostics.iter().next().unwrap();
+            assert_eq!(diagnostic.message, "could not parse `x` as a signed integer (i32)");
+            assert_eq!(diagnostic.global_span.text().as_ref(), "x");
+            assert_eq!(diagnostic.global_span.location_string(), "2:5");
+        }
+        _ => panic!("expected a recoverable error"),
+    }
+}
+
+#[test]
+fn test_serialize() {
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo", rename_all="kebab-case")]
+    struct Foo {
+        my_integer: i32,
+        is_good: bool,
+    }
+
+    #[derive(Serialize, Eq, PartialEq, Debug)]
+    #[serde(rename_all="kebab-case")]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { w: u32, h: u32 },
+    }
+
+    let foo = Foo { my_integer: 5, is_good: true };
+    assert_eq!(to_string(&foo), "(foo my-integer: 5 is-good: true)");
+    assert_eq!(deserialize_strict::<Foo>(&to_sexpr(&foo)).unwrap(), foo);
+
+    let map: BTreeMap<_, _> = vec![("a", 1), ("b", 2)].into_iter().collect();
+    assert_eq!(to_string(&map), "(a: 1 b: 2)");
+    assert_eq!(to_string(&vec![Some(1), None]), "(1 nil)");
+    let strings = vec![Some("nil".to_string()), None];
+    assert_eq!(to_string(&strings), "(\"nil\" nil)");
+    run_test_good(&to_string(&strings), strings);
+    assert_eq!(to_string(&(true, "two words", 'c')), "(true \"two words\" c)");
+    assert_eq!(to_string(&vec![Shape::Empty, Shape::Circle(3), Shape::Rect { w: 1, h: 2 }]),
+               "((empty) (circle 3) (rect w: 1 h: 2))");
+
+    run_test_good(&to_string(&vec![vec![1, 2], vec![3]]), vec![vec![1, 2], vec![3]]);
+}
+
+#[test]
+fn test_text_fields() {
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="person")]
+    struct Person {
+        name: String,
+        initial: char,
+    }
+
+    run_test_good(r#"(person name:"Ada \"the\" Countess" initial:A)"#,
+                  Person { name: "Ada \"the\" Countess".into(), initial: 'A' });
+    run_test_good(r#"(person name:ada initial:":")"#, Person { name: "ada".into(), initial: ':' });
+
+    let person = Person { name: "two words".into(), initial: ';' };
+    assert_eq!(deserialize_strict::<Person>(&to_sexpr(&person)).unwrap(), person);
+
+    run_test_bad::<Person>("(person name:(ada) initial:A)",
+                           &["expected to parse string but found (ada) for field `name`"]);
+    run_test_bad::<Person>("(person name:ada initial:AB)",
+                           &["could not parse `AB` as a character for field `initial`"]);
+    run_test_bad::<Person>(r#"(person name:ada initial:"AB")"#,
+                           &[r#"could not parse "AB" as a character for field `initial`"#]);
+}
+
+#[test]
+fn test_deserialize_any() {
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use serde::de::{SeqAccess, Visitor};
+
+    #[derive(PartialEq, Debug)]
+    enum Dynamic {
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Str(String),
+        List(Vec<Dynamic>),
+    }
+
+    struct DynamicVisitor;
+
+    impl<'de> Visitor<'de> for DynamicVisitor {
+        type Value = Dynamic;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("any value")
+        }
+
+        fn visit_bool<E>(self, v: bool) -> Result<Dynamic, E> { Ok(Dynamic::Bool(v)) }
+        fn visit_i64<E>(self, v: i64) -> Result<Dynamic, E> { Ok(Dynamic::Int(v)) }
+        fn visit_f64<E>(self, v: f64) -> Result<Dynamic, E> { Ok(Dynamic::Float(v)) }
+        fn visit_str<E>(self, v: &str) -> Result<Dynamic, E> { Ok(Dynamic::Str(v.into())) }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Dynamic, A::Error> {
+            let mut items = vec![];
+            while let Some(item) = seq.next_element()? {
+                items.push(item);
+            }
+            Ok(Dynamic::List(items))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Dynamic {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Dynamic, D::Error> {
+            deserializer.deserialize_any(DynamicVisitor)
+        }
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse(r#"(true -0x10 2.5 name "two words" (1))"#, &[], None);
+    assert_eq!(deserialize_strict::<Dynamic>(&roots[0]).unwrap(),
+               Dynamic::List(vec![Dynamic::Bool(true),
+                                  Dynamic::Int(-16),
+                                  Dynamic::Float(2.5),
+                                  Dynamic::Str("name".into()),
+                                  Dynamic::Str("two words".into()),
+                                  Dynamic::List(vec![Dynamic::Int(1)])]));
+
+    // `flatten` buffers unknown fields through `deserialize_any`
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Plugin {
+        name: String,
+        #[serde(flatten)]
+        config: BTreeMap<String, i64>,
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse("(name:lint level:3 depth:10)", &[":"], None);
+    let plugin = deserialize_strict::<Plugin>(&roots[0]).unwrap();
+    assert_eq!(plugin.name, "lint");
+    assert_eq!(plugin.config, vec![("depth".to_string(), 10), ("level".to_string(), 3)].into_iter().collect());
+}
+
+#[test]
+fn test_from_str() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="point")]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    assert_eq!(from_str::<Point>("(point x:1 y:2)", None).unwrap(), Point { x: 1, y: 2 });
+
+    let messages = |result: DeserializeResult<Point>| match result {
+        DeserializeResult::AllGood(_) => panic!("expected to fail"),
+        DeserializeResult::CouldRecover(_, bag) |
+        DeserializeResult::CouldntRecover(bag) => {
+            bag.iter().map(|d| (d.message.clone(), d.global_span.text().to_string())).collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(messages(from_str("", None)),
+               vec![("expected a value but found no values".to_string(), "".to_string())]);
+    assert_eq!(messages(from_str("(point x:1 y:2) (a) b", Some("points"))),
+               vec![("expected a single value but found multiple values".to_string(), "(a) b".to_string())]);
+    assert_eq!(messages(from_str("(point x:1 y:two)", None)),
+               vec![("could not parse `two` as a signed integer (i32) for field `y`".to_string(), "two".to_string())]);
+    // Parse errors are kept alongside deserialization errors
+    assert_eq!(messages(from_str("(point x:1 y:2", None))[0].0, "unclosed list");
+}
+
+#[test]
+fn test_bare_unit_variant() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename_all="kebab-case")]
+    enum Color {
+        Red,
+        Green,
+        Custom(String),
+    }
+
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="cfg")]
+    struct Cfg {
+        color: Color,
+    }
+
+    run_test_good("(cfg color:red)", Cfg { color: Color::Red });
+    run_test_good("(cfg color:(green))", Cfg { color: Color::Green });
+    run_test_good("(cfg color:(custom teal))", Cfg { color: Color::Custom("teal".into()) });
+
+    let ParseResult { roots, .. } = ::simple_parse("(cfg color:blue) (cfg color:custom)", &[":"], None);
+    let messages: Vec<_> = roots.iter()
+        .map(|r| deserialize_strict::<Cfg>(r).unwrap_err().iter().next().unwrap().message.clone())
+        .collect();
+    assert_eq!(messages, vec!["blue is not a variant name for enum Color",
+                              "expected a value for this variant"]);
+}
+
+#[test]
+fn test_interpolation() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="paths")]
+    struct Paths {
+        cache: String,
+        name: String,
+    }
+
+    let resolver = |var: &str| if var == "HOME" { Some("/home/me".to_string()) } else { None };
+
+    let ParseResult { roots, .. } =
+        ::simple_parse("(paths cache:\"${HOME}/x\" name:\"${HOME}\")", &[":"], None);
+    assert_eq!(deserialize_with_interpolation::<Paths, _>(&roots[0], resolver).unwrap(),
+               Paths { cache: "/home/me/x".to_string(), name: "/home/me".to_string() });
+
+    let ParseResult { roots, .. } =
+        ::simple_parse("(paths cache:\"${XDG_CACHE}/x\" name:me)", &[":"], None);
+    match deserialize_with_interpolation::<Paths, _>(&roots[0], resolver) {
+        DeserializeResult::CouldRecover(paths, bag) => {
+            assert_eq!(paths.cache, "${XDG_CACHE}/x");
+            let messages = bag.iter()
+                .map(|d| (d.message.clone(), d.global_span.text().to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(messages,
+                       vec![("unresolved variable `XDG_CACHE` for field `cache`".to_string(),
+                             "\"${XDG_CACHE}/x\"".to_string())]);
+        }
+        _ => panic!("expected to recover"),
+    }
+}
+
+#[test]
+fn test_stray_struct_values() {
+    #[derive(Deserialize, Eq, PartialEq, Debug)]
+    #[serde(rename="foo")]
+    struct Foo {
+        x: i32,
+        y: i32,
+    }
+
+    let ParseResult { roots, .. } = ::simple_parse("(foo x:1 junk y:2)", &[":"], None);
+    match deserialize::<Foo>(&roots[0]) {
+        DeserializeResult::CouldRecover(foo, bag) => {
+            assert_eq!(foo, Foo { x: 1, y: 2 });
+            let messages = bag.iter()
+                .map(|d| (d.message.clone(), d.global_span.text().to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(messages,
+                       vec![("unexpected `junk` in struct foo, expected `key:value` entries".to_string(),
+                             "junk".to_string())]);
+        }
+        _ => panic!("expected to recover"),
+    }
+    assert!(deserialize_strict::<Foo>(&roots[0]).is_err());
+
+    // A trailing key with no value points at the key
+    let ParseResult { roots, .. } = ::simple_parse("(foo x:1 y:)", &[":"], None);
+    let bag = deserialize_strict::<Foo>(&roots[0]).unwrap_err();
+    let diagnostic = bag.iter().next().unwrap();
+    assert_eq!((diagnostic.message.as_str(), diagnostic.global_span.text().as_ref()), ("expected value", "y"));
+}
+
+#[test]
+fn test_quoted_scalars() {
+    let ParseResult { roots, .. } = ::simple_parse(r#""true" "42" "0x10" "forty""#, &[], None);
+
+    // Quoted values are rejected by default
+    match deserialize::<bool>(&roots[0]) {
+        DeserializeResult::CouldRecover(_, bag) => {
+            assert!(bag.iter().next().unwrap().message.starts_with("expected to parse boolean value"));
+        }
+        _ => panic!("expected the quoted value to be rejected"),
+    }
+
+    let options = DeserializeOptions::new().with_quoted_scalars(true);
+    match deserialize_with_options::<bool>(&roots[0], &options) {
+        DeserializeResult::CouldRecover(value, bag) => {
+            assert_eq!(value, true);
+            let diagnostic = bag.iter().next().unwrap();
+            assert!(diagnostic.is_info());
+            assert_eq!(diagnostic.message, r#"read boolean value from quoted string "true""#);
+        }
+        _ => panic!("expected a note about the coercion"),
+    }
+    match deserialize_with_options::<i32>(&roots[1], &options) {
+        DeserializeResult::CouldRecover(value, bag) => {
+            assert_eq!(value, 42);
+            assert_eq!(bag.len(), 1);
+            assert!(bag.iter().next().unwrap().is_info());
+        }
+        _ => panic!("expected a note about the coercion"),
+    }
+    match deserialize_with_options::<u8>(&roots[2], &options) {
+        DeserializeResult::CouldRecover(value, _) => assert_eq!(value, 16),
+        _ => panic!("expected a note about the coercion"),
+    }
+
+    // A string that doesn't parse is still an error
+    match deserialize_with_options::<i32>(&roots[3], &options) {
+        DeserializeResult::CouldRecover(value, bag) => {
+            assert_eq!(value, 0);
+            assert!(bag.contains_errors());
+        }
+        _ => panic!("expected the string to be rejected"),
+    }
+}
+
+#[cfg(all(feature = "num-bigint", feature = "num-rational"))]
+#[test]
+fn test_big_numbers() {
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(rename="measure")]
+    struct Measure {
+        #[serde(deserialize_with="super::num::bigint::deserialize")]
+        count: BigInt,
+        #[serde(deserialize_with="super::num::rational::deserialize")]
+        ratio: BigRational,
+    }
+
+    let ParseResult { roots, .. } =
+        ::simple_parse("(measure count:1234567890123456789012345678901234567890 ratio:3/4)", &[":"], None);
+    assert_eq!(deserialize_strict::<Measure>(&roots[0]).unwrap(),
+               Measure {
+                   count: "1234567890123456789012345678901234567890".parse().unwrap(),
+                   ratio: BigRational::new(3.into(), 4.into()),
+               });
+
+    let ParseResult { roots, .. } = ::simple_parse("(measure count:1.5 ratio:3/4)", &[":"], None);
+    let bag = deserialize_strict::<Measure>(&roots[0]).unwrap_err();
+    assert_eq!(bag.iter().next().unwrap().message, "could not parse `1.5` as a big integer");
+}
@@ -0,0 +1,147 @@
+use std::mem;
+
+use super::{placeholder, Sexpr};
+use parse::Span;
+use token::{ListType, TokenInfo};
+
+/// Rebuilds an `Sexpr` tree bottom-up, producing an owned tree.
+///
+/// `fold` folds the children of an expression first, then hands the folded
+/// children to the method for that expression's variant.  Every method
+/// defaults to rebuilding the expression unchanged, so an implementation
+/// only needs to override the variants it transforms.
+///
+/// A list keeps the span between its delimiters.  Unary operators and
+/// expressions with metadata have their spans recomputed to cover their
+/// folded children, as long as those still point into the same source.
+pub trait SexprFold {
+    fn fold(&mut self, mut sexpr: Sexpr) -> Sexpr {
+        match sexpr {
+            Sexpr::List {
+                list_type,
+                opening_token,
+                closing_token,
+                ref mut children,
+                ref mut span,
+            } => {
+                let children = children.drain(..).map(|c| self.fold(c)).collect();
+                let span = mem::replace(span, Span::empty());
+                self.fold_list(list_type, opening_token, closing_token, children, span)
+            }
+            Sexpr::UnaryOperator { op, ref mut child, ref span } => {
+                let child = self.fold(mem::replace(&mut **child, placeholder()));
+                let span = covering_span(span, child.span());
+                self.fold_unary_operator(op, child, span)
+            }
+            Sexpr::WithMeta { caret, ref mut meta, ref mut expr, ref span } => {
+                let meta = self.fold(mem::replace(&mut **meta, placeholder()));
+                let expr = self.fold(mem::replace(&mut **expr, placeholder()));
+                let span = covering_span(span, expr.span());
+                self.fold_with_meta(caret, meta, expr, span)
+            }
+            Sexpr::Terminal(token, ref mut span) => {
+                self.fold_terminal(token, mem::replace(span, Span::empty()))
+            }
+            Sexpr::String(token, ref mut span) => {
+                self.fold_string(token, mem::replace(span, Span::empty()))
+            }
+        }
+    }
+
+    fn fold_list(&mut self,
+                 list_type: ListType,
+                 opening_token: TokenInfo,
+                 closing_token: TokenInfo,
+                 children: Vec<Sexpr>,
+                 span: Span)
+                 -> Sexpr {
+        Sexpr::List {
+            list_type: list_type,
+            opening_token: opening_token,
+            closing_token: closing_token,
+            children: children,
+            span: span,
+        }
+    }
+
+    fn fold_unary_operator(&mut self, op: TokenInfo, child: Sexpr, span: Span) -> Sexpr {
+        Sexpr::UnaryOperator {
+            op: op,
+            child: Box::new(child),
+            span: span,
+        }
+    }
+
+    fn fold_with_meta(&mut self, caret: TokenInfo, meta: Sexpr, expr: Sexpr, span: Span) -> Sexpr {
+        Sexpr::WithMeta {
+            caret: caret,
+            meta: Box::new(meta),
+            expr: Box::new(expr),
+            span: span,
+        }
+    }
+
+    fn fold_terminal(&mut self, token: TokenInfo, span: Span) -> Sexpr {
+        Sexpr::Terminal(token, span)
+    }
+
+    fn fold_string(&mut self, token: TokenInfo, span: Span) -> Sexpr {
+        Sexpr::String(token, span)
+    }
+}
+
+// Extends `outer` to cover `inner`, unless `inner` was built from some
+// other source text.
+fn covering_span(outer: &Span, inner: &Span) -> Span {
+    let same_source = outer.full_text.as_ptr() == inner.full_text.as_ptr() &&
+                      outer.full_text.len() == inner.full_text.len() &&
+                      outer.file == inner.file;
+    if same_source {
+        Span::from_spans(outer, inner)
+    } else {
+        outer.clone()
+    }
+}
+
+#[test]
+fn constant_folding() {
+    struct AddIntegers;
+
+    impl SexprFold for AddIntegers {
+        fn fold_list(&mut self,
+                     list_type: ListType,
+                     opening_token: TokenInfo,
+                     closing_token: TokenInfo,
+                     children: Vec<Sexpr>,
+                     span: Span)
+                     -> Sexpr {
+            let is_add = children.first().map_or(false, |c| c.text().as_ref() == "+");
+            let terms: Option<Vec<i64>> = children.iter()
+                .skip(1)
+                .map(|c| c.text().as_ref().parse().ok())
+                .collect();
+            match terms {
+                Some(terms) if is_add => {
+                    let sum: i64 = terms.iter().sum();
+                    ::simple_parse(sum.to_string(), &[], None).roots.remove(0)
+                }
+                _ => {
+                    Sexpr::List {
+                        list_type: list_type,
+                        opening_token: opening_token,
+                        closing_token: closing_token,
+                        children: children,
+                        span: span,
+                    }
+                }
+            }
+        }
+    }
+
+    struct Identity;
+    impl SexprFold for Identity {}
+
+    let ::Result { roots, .. } = ::simple_parse("(print (+ 1 (+ 2 3)) (+ 1 x))", &[], None);
+    assert_eq!(Identity.fold(roots[0].clone()), roots[0]);
+    assert_eq!(AddIntegers.fold(roots[0].clone()).to_source_string(), "(print 6 (+ 1 x))");
+}
@@ -0,0 +1,922 @@
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+
+use super::token::{ListType, TokenInfo, TokenType, quote_string, unescape_string};
+use super::parse::{Span, SexprKind};
+use super::diagnostic::DiagnosticBag;
+use super::serde_serialization::DeserializeResult;
+use tendril::StrTendril;
+
+mod fold;
+mod print;
+mod testing;
+mod visit;
+pub use self::fold::SexprFold;
+pub use self::print::{pretty_print, PrettyOptions};
+pub use self::visit::{SexprVisitor, Visit};
+
+/// The S-Expression tree type.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Sexpr {
+    /// An S-Expression List.
+    ///
+    /// Typically looks like `(...)`, `[...]`, or `{...}`.
+    List {
+        list_type: ListType,
+        opening_token: TokenInfo,
+        closing_token: TokenInfo,
+
+        children: Vec<Sexpr>,
+        span: Span,
+    },
+
+    /// An s-expression unary operator applied to the expression after it.
+    ///
+    /// Examples: `'foo`, `,@(bar)`.  Only produced for the operators listed in
+    /// `TokenizeOptions::unary_operators`.
+    UnaryOperator {
+        op: TokenInfo,
+        child: Box<Sexpr>,
+        span: Span,
+    },
+
+    /// An expression with metadata attached, like `^{:doc "..."} foo`.
+    ///
+    /// Only produced when metadata parsing is enabled in `ParseOptions`.
+    WithMeta {
+        caret: TokenInfo,
+        meta: Box<Sexpr>,
+        expr: Box<Sexpr>,
+        span: Span,
+    },
+
+    /// A "terminal" node in the tree.
+    ///
+    /// Examples: `5.0`, `foo`, `asdlkh23y823ysd`.
+    Terminal(TokenInfo, Span),
+
+    /// A "string" node in the tree.
+    ///
+    /// Examples: `"foo"`
+    String(TokenInfo, Span),
+}
+
+/// A numeric value classified from the text of a terminal.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+impl Sexpr {
+    /// Returns the text that built this s-expression
+    ///
+    /// This is a shortcut for `.span().text()`.
+    pub fn text(&self) -> StrTendril {
+        self.span().text()
+    }
+
+    /// Returns an easily-matchable `SexprKind` value.
+    pub fn kind(&self) -> SexprKind {
+        match self {
+            &Sexpr::List { .. } => SexprKind::List,
+            &Sexpr::UnaryOperator { .. } => SexprKind::UnaryOperator,
+            &Sexpr::WithMeta { .. } => SexprKind::WithMeta,
+            &Sexpr::String(_, _) => SexprKind::String,
+            &Sexpr::Terminal(_, _) => SexprKind::Terminal,
+        }
+    }
+
+    /// Returns the span over the source code that this s-expression encompasses
+    pub fn span(&self) -> &Span {
+        match self {
+            &Sexpr::List { ref span, .. } => span,
+            &Sexpr::UnaryOperator { ref span, .. } => span,
+            &Sexpr::WithMeta { ref span, .. } => span,
+            &Sexpr::String(_, ref span) |
+            &Sexpr::Terminal(_, ref span) => span,
+        }
+    }
+
+    /// Compares two s-expressions by structure and text, ignoring spans, token
+    /// positions, and any whitespace between tokens.
+    ///
+    /// A formatter that only changes trivia should always produce a tree that
+    /// is `eq_ignoring_trivia` to the original.
+    pub fn eq_ignoring_trivia(&self, other: &Sexpr) -> bool {
+        match (self, other) {
+            (&Sexpr::List { list_type: ref t1, children: ref c1, .. },
+             &Sexpr::List { list_type: ref t2, children: ref c2, .. }) => {
+                t1 == t2 && c1.len() == c2.len() &&
+                c1.iter().zip(c2.iter()).all(|(a, b)| a.eq_ignoring_trivia(b))
+            }
+            (&Sexpr::UnaryOperator { op: ref o1, child: ref c1, span: ref s1 },
+             &Sexpr::UnaryOperator { op: ref o2, child: ref c2, span: ref s2 }) => {
+                let op_text = |op: &TokenInfo, span: &Span| {
+                    span.full_text.subtendril(op.byte_offset as u32, op.length)
+                };
+                op_text(o1, s1) == op_text(o2, s2) && c1.eq_ignoring_trivia(c2)
+            }
+            (&Sexpr::WithMeta { meta: ref m1, expr: ref e1, .. },
+             &Sexpr::WithMeta { meta: ref m2, expr: ref e2, .. }) => {
+                m1.eq_ignoring_trivia(m2) && e1.eq_ignoring_trivia(e2)
+            }
+            (&Sexpr::Terminal(_, ref s1), &Sexpr::Terminal(_, ref s2)) |
+            (&Sexpr::String(_, ref s1), &Sexpr::String(_, ref s2)) => s1.text() == s2.text(),
+            _ => false,
+        }
+    }
+
+    /// Compares two s-expressions by list type, terminal and string text, and
+    /// child structure, ignoring where in the source either one came from.
+    ///
+    /// This is the same comparison as `eq_ignoring_trivia`; see
+    /// `StructuralSexpr` for a wrapper that uses it for `Eq` and `Hash`.
+    pub fn structural_eq(&self, other: &Sexpr) -> bool {
+        self.eq_ignoring_trivia(other)
+    }
+
+    /// Converts this s-expression into a JSON value.
+    ///
+    /// Lists become arrays, strings become their unescaped contents, and
+    /// terminals become numbers or booleans if they parse as one, and strings
+    /// otherwise.
+    /// Unary operators become a two-element array of the operator and its child,
+    /// and expressions with metadata become an object with `meta` and `expr` keys.
+    pub fn to_json_value(&self) -> ::serde_json::Value {
+        use serde_json::{Number, Value};
+
+        match self {
+            &Sexpr::List { ref children, .. } => {
+                Value::Array(children.iter().map(Sexpr::to_json_value).collect())
+            }
+            &Sexpr::UnaryOperator { ref op, ref child, ref span } => {
+                let op = span.full_text.subtendril(op.byte_offset as u32, op.length);
+                Value::Array(vec![Value::String(op.as_ref().into()), child.to_json_value()])
+            }
+            &Sexpr::WithMeta { ref meta, ref expr, .. } => {
+                json!({
+                    "meta": meta.to_json_value(),
+                    "expr": expr.to_json_value(),
+                })
+            }
+            &Sexpr::String(_, ref span) => Value::String(unescape_string(span.text().as_ref())),
+            &Sexpr::Terminal(_, ref span) => {
+                let text = span.text();
+                let text = text.as_ref();
+                if let Ok(b) = text.parse::<bool>() {
+                    Value::Bool(b)
+                } else if let Ok(i) = text.parse::<i64>() {
+                    Value::from(i)
+                } else if let Some(n) = text.parse::<f64>().ok().and_then(Number::from_f64) {
+                    Value::Number(n)
+                } else {
+                    Value::String(text.into())
+                }
+            }
+        }
+    }
+
+    /// Deserializes this s-expression into a value of type `T`.
+    ///
+    /// This is a shortcut for `snoot::serde_serialization::deserialize(self)`.
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// extern crate snoot;
+    ///
+    /// #[derive(Deserialize, Debug, PartialEq)]
+    /// #[serde(rename = "config")]
+    /// struct Config {
+    ///     port: u32,
+    ///     verbose: bool,
+    /// }
+    ///
+    /// fn main() {
+    ///     let result = snoot::simple_parse("(config port:8080 verbose:true)", &[":"], None);
+    ///     let config: Config = result.roots[0].deserialize_into().unwrap();
+    ///     assert_eq!(config, Config { port: 8080, verbose: true });
+    /// }
+    /// ```
+    pub fn deserialize_into<'a, T: ::serde::Deserialize<'a>>(&'a self) -> DeserializeResult<T> {
+        ::serde_serialization::deserialize(self)
+    }
+
+    /// Renders this s-expression back into source text.
+    ///
+    /// Children of a list are separated by a single space, and terminals and
+    /// strings are written exactly as they appeared in the source.
+    /// The tree is walked with an explicit stack, so very deeply nested
+    /// expressions can be rendered without overflowing the call stack.
+    pub fn to_source_string(&self) -> String {
+        self.render(false)
+    }
+
+    // Shared by `to_source_string` and `Display`; when `canonical_strings` is
+    // set, string literals are unescaped and re-quoted instead of copied.
+    fn render(&self, canonical_strings: bool) -> String {
+        enum Item<'a> {
+            Node(&'a Sexpr),
+            Close(ListType),
+            Space,
+        }
+
+        let mut out = String::new();
+        let mut stack = vec![Item::Node(self)];
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Space => out.push(' '),
+                Item::Close(list_type) => out.push_str(&list_type.to_string(false)),
+                Item::Node(&Sexpr::List { list_type, ref children, .. }) => {
+                    out.push_str(&list_type.to_string(true));
+                    stack.push(Item::Close(list_type));
+                    for (i, child) in children.iter().enumerate().rev() {
+                        stack.push(Item::Node(child));
+                        if i != 0 {
+                            stack.push(Item::Space);
+                        }
+                    }
+                }
+                Item::Node(&Sexpr::UnaryOperator { ref op, ref child, ref span }) => {
+                    out.push_str(span.full_text.subtendril(op.byte_offset as u32, op.length).as_ref());
+                    stack.push(Item::Node(child));
+                }
+                Item::Node(&Sexpr::WithMeta { ref caret, ref meta, ref expr, ref span }) => {
+                    out.push_str(span.full_text.subtendril(caret.byte_offset as u32, caret.length).as_ref());
+                    stack.push(Item::Node(expr));
+                    stack.push(Item::Space);
+                    stack.push(Item::Node(meta));
+                }
+                Item::Node(&Sexpr::String(_, ref span)) if canonical_strings => {
+                    out.push_str(&quote_string(&unescape_string(span.text().as_ref())))
+                }
+                Item::Node(&Sexpr::Terminal(_, ref span)) |
+                Item::Node(&Sexpr::String(_, ref span)) => out.push_str(span.text().as_ref()),
+            }
+        }
+        out
+    }
+
+    /// Returns an iterator over every expression nested inside this one,
+    /// in pre-order.  The expression itself is not included.
+    pub fn descendants(&self) -> Descendants {
+        let mut stack = vec![];
+        push_children(self, &mut stack);
+        Descendants { stack: stack }
+    }
+
+    /// Returns an iterator over this expression and then every expression
+    /// nested inside it, in pre-order.
+    ///
+    /// Like `descendants`, this is lazy: it only holds the siblings that
+    /// haven't been visited yet, not the whole tree.
+    pub fn iter_preorder(&self) -> Descendants {
+        Descendants { stack: vec![self] }
+    }
+
+    /// Returns the innermost expression whose span contains the byte at
+    /// `offset`, or `None` if this expression doesn't contain it.
+    ///
+    /// Useful for finding the expression under an editor's cursor.
+    pub fn node_at_offset(&self, offset: u32) -> Option<&Sexpr> {
+        if !self.span().contains_byte(offset) {
+            return None;
+        }
+        let mut children = vec![];
+        push_children(self, &mut children);
+        children.into_iter()
+            .filter_map(|child| child.node_at_offset(offset))
+            .next()
+            .or(Some(self))
+    }
+
+    /// Counts the nested expressions for which `pred` returns true.
+    pub fn count_matching<F: Fn(&Sexpr) -> bool>(&self, pred: F) -> usize {
+        self.descendants().filter(|s| pred(s)).count()
+    }
+
+    /// Returns the type of list (paren, bracket, or brace) if this is a list.
+    pub fn as_list_type(&self) -> Option<ListType> {
+        match self {
+            &Sexpr::List { list_type, .. } => Some(list_type),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of the first child if this is a list whose first
+    /// child is a terminal, like `def` in `(def x 5)`.
+    pub fn head_symbol(&self) -> Option<StrTendril> {
+        match self {
+            &Sexpr::List { ref children, .. } => {
+                match children.first() {
+                    Some(&Sexpr::Terminal(_, ref span)) => Some(span.text()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the last token that contributed to building this expression
+    pub fn last_token(&self) -> &TokenInfo {
+        match self {
+            &Sexpr::List { ref closing_token, .. } => closing_token,
+            &Sexpr::UnaryOperator { ref child, .. } => child.last_token(),
+            &Sexpr::WithMeta { ref expr, .. } => expr.last_token(),
+            &Sexpr::String(ref token, _) |
+            &Sexpr::Terminal(ref token, _) => token,
+        }
+    }
+
+    /// Returns the first token that contrtbuted to building this expression
+    pub fn first_token(&self) -> &TokenInfo {
+        match self {
+            &Sexpr::List { ref opening_token, .. } => opening_token,
+            &Sexpr::UnaryOperator { ref op, .. } => op,
+            &Sexpr::WithMeta { ref caret, .. } => caret,
+            &Sexpr::String(ref token, _) |
+            &Sexpr::Terminal(ref token, _) => token,
+        }
+    }
+
+    /// Classifies this terminal as an integer or a float, using the same
+    /// `str::parse` rules as `expect_int` and `expect_float`.
+    ///
+    /// Integers that don't fit in an `i64` are returned as floats.  Words that
+    /// `f64` would accept, like `inf` and `nan`, are not treated as numbers.
+    pub fn as_number(&self) -> Option<Number> {
+        let span = match self {
+            &Sexpr::Terminal(_, ref span) => span,
+            _ => return None,
+        };
+        let text = span.text();
+        let text = text.as_ref();
+        if let Ok(i) = text.parse() {
+            return Some(Number::Integer(i));
+        }
+        if !text.bytes().any(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        text.parse().ok().map(Number::Float)
+    }
+
+    pub fn expect_int(&self, diagnostics: &mut DiagnosticBag) -> Option<i64> {
+        if let &Sexpr::Terminal(_, ref span) = self {
+            if let Ok(parsed) = span.text().as_ref().parse() {
+                Some(parsed)
+            } else {
+                diagnostics
+                    .add(diagnostic!(span, "Expected integer, failed to parse `{}`", span.text()));
+                None
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find an integer, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+
+    pub fn expect_float(&self, diagnostics: &mut DiagnosticBag) -> Option<f64> {
+        if let &Sexpr::Terminal(_, ref span) = self {
+            if let Ok(parsed) = span.text().as_ref().parse() {
+                Some(parsed)
+            } else {
+                diagnostics
+                    .add(diagnostic!(span, "Expected number, failed to parse `{}`", span.text()));
+                None
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find a number, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+
+    /// Expects an integer literal of any size, like
+    /// `-1234567890123456789012345678901234567890`, and returns its text
+    /// without a leading `+`, for parsing with a big integer library.
+    pub fn expect_bigint(&self, diagnostics: &mut DiagnosticBag) -> Option<String> {
+        if let &Sexpr::Terminal(_, ref span) = self {
+            match integer_literal(span.text().as_ref()) {
+                Some(int) => Some(int.to_string()),
+                None => {
+                    diagnostics.add(diagnostic!(span, "Expected integer, failed to parse `{}`", span.text()));
+                    None
+                }
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find an integer, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+
+    /// Expects an exact rational literal like `3/4` or `-10/3`, and returns
+    /// its numerator and denominator as they would be returned by
+    /// `expect_bigint`.  The denominator may not be negative or zero.
+    ///
+    /// `/` must not be one of the tokenizer splitters, or `3/4` is read as
+    /// three terminals.
+    pub fn expect_rational(&self, diagnostics: &mut DiagnosticBag) -> Option<(String, String)> {
+        if let &Sexpr::Terminal(_, ref span) = self {
+            match rational_literal(span.text().as_ref()) {
+                Some((numer, denom)) => Some((numer.to_string(), denom.to_string())),
+                None => {
+                    diagnostics.add(diagnostic!(span, "Expected rational, failed to parse `{}`", span.text()));
+                    None
+                }
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find a rational, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+
+    /// Returns the value of this terminal if it is an integer literal of any size.
+    #[cfg(feature = "num-bigint")]
+    pub fn as_bigint(&self) -> Option<::num_bigint::BigInt> {
+        match self {
+            &Sexpr::Terminal(_, ref span) => integer_literal(span.text().as_ref()).and_then(|i| i.parse().ok()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of this terminal if it is a rational literal like
+    /// `3/4`, or an integer literal of any size.
+    #[cfg(feature = "num-rational")]
+    pub fn as_rational(&self) -> Option<::num_rational::BigRational> {
+        let text = match self {
+            &Sexpr::Terminal(_, ref span) => span.text(),
+            _ => return None,
+        };
+        let (numer, denom) = match rational_literal(text.as_ref()) {
+            Some((numer, denom)) => (numer, denom),
+            None => (integer_literal(text.as_ref())?, "1"),
+        };
+        Some(::num_rational::BigRational::new(numer.parse().ok()?, denom.parse().ok()?))
+    }
+
+    pub fn expect_list(&self, diagnostics: &mut DiagnosticBag) -> Option<&[Sexpr]> {
+        if let &Sexpr::List { ref children, .. } = self {
+            Some(children)
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find a list, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+
+    pub fn expect_terminal(&self, symbol: &str, diagnostics: &mut DiagnosticBag) -> Option<()> {
+        if let &Sexpr::Terminal(_, ref span) = self {
+            if symbol == span.text().as_ref() {
+                Some(())
+            } else {
+                diagnostics.add(diagnostic!(span, "Expected terminal `{}` found `{}`", symbol, span.text()));
+                None
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected terminal `{}`", symbol));
+            None
+        }
+    }
+
+    /// Expects a terminal matching one of `options`, returning the index of
+    /// the option it matched.
+    pub fn expect_terminal_oneof(&self,
+                                 options: &[&str],
+                                 diagnostics: &mut DiagnosticBag)
+                                 -> Option<usize> {
+        let expected = options.iter().map(|o| format!("`{}`", o)).collect::<Vec<_>>().join(", ");
+        if let &Sexpr::Terminal(_, ref span) = self {
+            let text = span.text();
+            let found = options.iter().position(|&o| o == text.as_ref());
+            if found.is_none() {
+                diagnostics.add(diagnostic!(span, "Expected one of {} found `{}`", expected, text));
+            }
+            found
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected one of {}", expected));
+            None
+        }
+    }
+
+    pub fn expect_list_with_symbol(&self,
+                                   symbol: &str,
+                                   diagnostics: &mut DiagnosticBag)
+                                   -> Option<&[Sexpr]> {
+        if let &Sexpr::List { ref children, .. } = self {
+            if children.len() == 0 {
+                diagnostics.add(diagnostic!(self.span(), "Expected a list with symbol `{}` but found an empty list", symbol));
+                None
+            } else {
+                children[0]
+                    .expect_terminal(symbol, diagnostics)
+                    .map(|_| &children[1..])
+            }
+        } else {
+            diagnostics.add(diagnostic!(self.span(), "Expected to find a list, but found {} instead", self.kind().name()));
+            None
+        }
+    }
+}
+
+// Returns `text` without a leading `+` if it is a decimal integer literal.
+fn integer_literal(text: &str) -> Option<&str> {
+    let unsigned = if text.starts_with('-') || text.starts_with('+') {
+        &text[1..]
+    } else {
+        text
+    };
+    if unsigned.is_empty() || !unsigned.bytes().all(|b| b.is_ascii_digit()) {
+        None
+    } else if text.starts_with('+') {
+        Some(unsigned)
+    } else {
+        Some(text)
+    }
+}
+
+// Splits a rational literal like `3/4` into its numerator and denominator.
+fn rational_literal(text: &str) -> Option<(&str, &str)> {
+    let slash = text.find('/')?;
+    let numer = integer_literal(&text[..slash])?;
+    let denom = &text[slash + 1..];
+    let is_digits = !denom.is_empty() && denom.bytes().all(|b| b.is_ascii_digit());
+    if is_digits && denom.bytes().any(|b| b != b'0') {
+        Some((numer, denom))
+    } else {
+        None
+    }
+}
+
+/// A pre-order iterator over the expressions nested inside an `Sexpr`.
+///
+/// Created by `Sexpr::descendants` and `Sexpr::iter_preorder`.
+pub struct Descendants<'a> {
+    stack: Vec<&'a Sexpr>,
+}
+
+fn push_children<'a>(sexpr: &'a Sexpr, stack: &mut Vec<&'a Sexpr>) {
+    match sexpr {
+        &Sexpr::List { ref children, .. } => stack.extend(children.iter().rev()),
+        &Sexpr::UnaryOperator { ref child, .. } => stack.push(child),
+        &Sexpr::WithMeta { ref meta, ref expr, .. } => {
+            stack.push(expr);
+            stack.push(meta);
+        }
+        &Sexpr::Terminal(..) |
+        &Sexpr::String(..) => {}
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Sexpr;
+
+    fn next(&mut self) -> Option<&'a Sexpr> {
+        let next = self.stack.pop();
+        if let Some(sexpr) = next {
+            push_children(sexpr, &mut self.stack);
+        }
+        next
+    }
+}
+
+/// Wraps an `Sexpr` so that `Eq` and `Hash` only look at its structure, as
+/// in `Sexpr::structural_eq`.
+///
+/// This makes it possible to deduplicate parsed forms, or to use them as
+/// `HashMap` keys, regardless of where they were parsed from.
+#[derive(Debug, Clone)]
+pub struct StructuralSexpr(pub Sexpr);
+
+impl PartialEq for StructuralSexpr {
+    fn eq(&self, other: &StructuralSexpr) -> bool {
+        self.0.structural_eq(&other.0)
+    }
+}
+
+impl Eq for StructuralSexpr {}
+
+impl Hash for StructuralSexpr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_structure(&self.0, state);
+    }
+}
+
+// Hashes exactly the parts of an expression that `structural_eq` compares.
+fn hash_structure<H: Hasher>(sexpr: &Sexpr, state: &mut H) {
+    match sexpr {
+        &Sexpr::List { list_type, ref children, .. } => {
+            0u8.hash(state);
+            list_type.hash(state);
+            children.len().hash(state);
+            for child in children {
+                hash_structure(child, state);
+            }
+        }
+        &Sexpr::UnaryOperator { ref op, ref child, ref span } => {
+            1u8.hash(state);
+            span.full_text.subtendril(op.byte_offset as u32, op.length).hash(state);
+            hash_structure(child, state);
+        }
+        &Sexpr::WithMeta { ref meta, ref expr, .. } => {
+            2u8.hash(state);
+            hash_structure(meta, state);
+            hash_structure(expr, state);
+        }
+        &Sexpr::Terminal(_, ref span) => {
+            3u8.hash(state);
+            span.text().hash(state);
+        }
+        &Sexpr::String(_, ref span) => {
+            4u8.hash(state);
+            span.text().hash(state);
+        }
+    }
+}
+
+/// Formats the expression as canonical source text.
+///
+/// This matches `to_source_string`, except that string literals are
+/// re-quoted with their escapes normalized.
+impl Display for Sexpr {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str(&self.render(true))
+    }
+}
+
+// An empty terminal to leave behind when moving an expression out of a tree;
+// `Sexpr` implements `Drop`, so it can't be destructured by value.
+fn placeholder() -> Sexpr {
+    let token = TokenInfo {
+        line_number: 0,
+        column_number: 0,
+        byte_offset: 0,
+        length: 0,
+        typ: TokenType::Atom,
+    };
+    Sexpr::Terminal(token, Span::empty())
+}
+
+/// Drops the tree without recursing, so that dropping a deeply nested tree
+/// can't overflow the stack.
+impl Drop for Sexpr {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_nested(self, &mut stack);
+        while let Some(mut sexpr) = stack.pop() {
+            take_nested(&mut sexpr, &mut stack);
+        }
+    }
+}
+
+// Moves the children of `sexpr` that have children of their own onto
+// `stack`, leaving only a shallow tree behind.
+fn take_nested(sexpr: &mut Sexpr, stack: &mut Vec<Sexpr>) {
+    fn is_nested(sexpr: &Sexpr) -> bool {
+        match *sexpr {
+            Sexpr::List { ref children, .. } => !children.is_empty(),
+            Sexpr::UnaryOperator { .. } |
+            Sexpr::WithMeta { .. } => true,
+            Sexpr::Terminal(..) |
+            Sexpr::String(..) => false,
+        }
+    }
+    fn take_boxed(child: &mut Box<Sexpr>, stack: &mut Vec<Sexpr>) {
+        if is_nested(child) {
+            stack.push(::std::mem::replace(&mut **child, placeholder()));
+        }
+    }
+
+    match *sexpr {
+        Sexpr::List { ref mut children, .. } => {
+            if children.iter().any(is_nested) {
+                stack.append(children);
+            }
+        }
+        Sexpr::UnaryOperator { ref mut child, .. } => take_boxed(child, stack),
+        Sexpr::WithMeta { ref mut meta, ref mut expr, .. } => {
+            take_boxed(meta, stack);
+            take_boxed(expr, stack);
+        }
+        Sexpr::Terminal(..) |
+        Sexpr::String(..) => {}
+    }
+}
+
+impl<'a> From<&'a Sexpr> for ::serde_json::Value {
+    fn from(sexpr: &'a Sexpr) -> ::serde_json::Value {
+        sexpr.to_json_value()
+    }
+}
+
+#[test]
+fn as_list_type_test() {
+    let ::Result { roots, diagnostics, .. } = ::simple_parse("[a] (a) a", &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots[0].as_list_type(), Some(ListType::Bracket));
+    assert_eq!(roots[1].as_list_type(), Some(ListType::Paren));
+    assert_eq!(roots[2].as_list_type(), None);
+}
+
+#[test]
+fn head_symbol_test() {
+    let ::Result { roots, diagnostics, .. } = ::simple_parse("(def x) () ((def) x) (\"def\") def", &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots[0].head_symbol().as_ref().map(|s| s.as_ref()), Some("def"));
+    assert!(roots[1..].iter().all(|r| r.head_symbol().is_none()));
+}
+
+#[test]
+fn expect_terminal_oneof_test() {
+    let ::Result { roots, .. } = ::simple_parse("write delete (read)", &[], None);
+    let modes = ["read", "write", "append"];
+    let mut diagnostics = DiagnosticBag::new();
+    assert_eq!(roots[0].expect_terminal_oneof(&modes, &mut diagnostics), Some(1));
+    assert!(diagnostics.is_empty());
+
+    assert_eq!(roots[1].expect_terminal_oneof(&modes, &mut diagnostics), None);
+    assert_eq!(roots[2].expect_terminal_oneof(&modes, &mut diagnostics), None);
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.message.clone()).collect();
+    assert_eq!(messages, vec!["Expected one of `read`, `write`, `append` found `delete`",
+                              "Expected one of `read`, `write`, `append`"]);
+}
+
+#[test]
+fn eq_ignoring_trivia_test() {
+    let ::Result { roots, diagnostics, .. } =
+        ::simple_parse("(a b) (  a   b  ) (a\n  b) (a c) [a b] (a b c)", &[], None);
+    assert!(diagnostics.is_empty());
+    assert!(roots[0].eq_ignoring_trivia(&roots[1]));
+    assert!(roots[1].eq_ignoring_trivia(&roots[2]));
+    assert!(roots[0] != roots[1]);
+    assert!(!roots[0].eq_ignoring_trivia(&roots[3]));
+    assert!(!roots[0].eq_ignoring_trivia(&roots[4]));
+    assert!(!roots[0].eq_ignoring_trivia(&roots[5]));
+}
+
+#[test]
+fn node_at_offset_test() {
+    let ::Result { roots, .. } = ::simple_parse("(def (f x) 'y)", &[], None);
+    let at = |offset| roots[0].node_at_offset(offset).map(|s| s.text().to_string());
+    assert_eq!(at(0), Some("(def (f x) 'y)".to_string()));
+    assert_eq!(at(2), Some("def".to_string()));
+    assert_eq!(at(4), Some("(def (f x) 'y)".to_string()));
+    assert_eq!(at(8), Some("x".to_string()));
+    assert_eq!(at(9), Some("(f x)".to_string()));
+    assert_eq!(at(14), None);
+}
+
+#[test]
+fn structural_eq_test() {
+    use std::collections::HashMap;
+
+    let ::Result { roots, .. } =
+        ::simple_parse("(a [b \"c\"]) x (a [b \"c\"]) (a (b \"c\"))", &[], None);
+    assert!(roots[0] != roots[2]);
+    assert!(roots[0].structural_eq(&roots[2]));
+    assert!(!roots[0].structural_eq(&roots[3]));
+
+    let mut counts = HashMap::new();
+    for root in roots {
+        *counts.entry(StructuralSexpr(root)).or_insert(0) += 1;
+    }
+    assert_eq!(counts.len(), 3);
+    let key = ::simple_parse("(a\n  [b \"c\"])", &[], None).roots.remove(0);
+    assert_eq!(counts[&StructuralSexpr(key)], 2);
+}
+
+#[test]
+fn to_json_value_test() {
+    let ::Result { roots, diagnostics, .. } = ::simple_parse("(a 1 true [2.5 -3] ())", &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots[0].to_json_value(), json!(["a", 1, true, [2.5, -3], []]));
+    assert_eq!(::serde_json::Value::from(&roots[0]), roots[0].to_json_value());
+
+    // Strings are unescaped
+    let ::Result { roots, .. } = ::simple_parse(r#"("a \"b\"\n")"#, &[], None);
+    assert_eq!(roots[0].to_json_value(), json!(["a \"b\"\n"]));
+}
+
+#[test]
+fn to_source_string_test() {
+    let ::Result { roots, diagnostics, .. } = ::simple_parse("(a  [b]\n {c  d} ())", &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(roots[0].to_source_string(), "(a [b] {c d} ())");
+}
+
+#[test]
+fn to_source_string_deep() {
+    let depth = 100000;
+    let source = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+    let ::Result { roots, diagnostics, .. } = ::simple_parse(source.as_str(), &[], None);
+    assert!(diagnostics.is_empty());
+
+    let rendered = roots[0].to_source_string();
+    assert_eq!(rendered.matches('(').count(), depth);
+    assert_eq!(rendered.matches(')').count(), depth);
+    assert_eq!(rendered, source);
+}
+
+#[test]
+fn count_matching_test() {
+    let ::Result { roots, .. } = ::simple_parse("(a (b c) d)", &[], None);
+    let terminals = roots[0].count_matching(|s| s.kind() == SexprKind::Terminal);
+    assert_eq!(terminals, 4);
+    let lists = roots[0].count_matching(|s| s.kind() == SexprKind::List);
+    assert_eq!(lists, 1);
+
+    let order: Vec<_> = roots[0].descendants().map(|s| s.text().to_string()).collect();
+    assert_eq!(order, vec!["a", "(b c)", "b", "c", "d"]);
+}
+
+#[test]
+fn iter_preorder_test() {
+    let ::Result { roots, .. } = ::simple_parse("(get-x (get-y p) \"get-z\" get)", &[], None);
+    let order: Vec<_> = roots[0].iter_preorder().map(|s| s.text().to_string()).collect();
+    assert_eq!(order[0], roots[0].text().as_ref());
+    assert_eq!(&order[1..], &["get-x", "(get-y p)", "get-y", "p", "\"get-z\"", "get"]);
+
+    let getters: Vec<_> = roots[0].iter_preorder()
+        .filter(|s| s.kind() == SexprKind::Terminal && s.text().starts_with("get-"))
+        .map(|s| s.text().to_string())
+        .collect();
+    assert_eq!(getters, vec!["get-x", "get-y"]);
+}
+
+#[test]
+fn big_numbers() {
+    let ::Result { roots, .. } =
+        ::simple_parse("(+1234567890123456789012345678901234567890 3/4 -10/3 1/0 x 1.5)", &[], None);
+    let children = match &roots[0] {
+        &Sexpr::List { ref children, .. } => children,
+        _ => panic!(),
+    };
+    // `3/4` stays a single terminal
+    assert_eq!(children.len(), 6);
+    assert_eq!(children[1].kind(), SexprKind::Terminal);
+    assert_eq!(children[1].text().as_ref(), "3/4");
+
+    let mut bag = DiagnosticBag::new();
+    assert_eq!(children[0].expect_bigint(&mut bag),
+               Some("1234567890123456789012345678901234567890".to_string()));
+    assert_eq!(children[1].expect_rational(&mut bag), Some(("3".to_string(), "4".to_string())));
+    assert_eq!(children[2].expect_rational(&mut bag), Some(("-10".to_string(), "3".to_string())));
+    assert!(bag.is_empty());
+
+    assert_eq!(children[1].expect_bigint(&mut bag), None);
+    assert_eq!(children[3].expect_rational(&mut bag), None);
+    assert_eq!(children[4].expect_rational(&mut bag), None);
+    assert_eq!(children[5].expect_bigint(&mut bag), None);
+    assert_eq!(bag.len(), 4);
+}
+
+#[cfg(all(feature = "num-bigint", feature = "num-rational"))]
+#[test]
+fn big_number_values() {
+    let ::Result { roots, .. } =
+        ::simple_parse("(-1234567890123456789012345678901234567890 6/8 5)", &[], None);
+    let children = match &roots[0] {
+        &Sexpr::List { ref children, .. } => children,
+        _ => panic!(),
+    };
+    let big: ::num_bigint::BigInt = "-1234567890123456789012345678901234567890".parse().unwrap();
+    assert_eq!(children[0].as_bigint(), Some(big));
+    assert_eq!(children[1].as_bigint(), None);
+    assert_eq!(children[1].as_rational(),
+               Some(::num_rational::BigRational::new(3.into(), 4.into())));
+    assert_eq!(children[2].as_rational(), Some(::num_rational::BigRational::from_integer(5.into())));
+}
+
+#[test]
+fn as_number_test() {
+    let ::Result { roots, .. } =
+        ::simple_parse("123 +7 -42 1.5 -0.25 1e10 2.5E-3 - + 1.2.3 inf nan abc 1a \"1\" (1)", &[], None);
+    let numbers: Vec<_> = roots.iter().map(Sexpr::as_number).collect();
+    assert_eq!(numbers,
+               vec![Some(Number::Integer(123)),
+                    Some(Number::Integer(7)),
+                    Some(Number::Integer(-42)),
+                    Some(Number::Float(1.5)),
+                    Some(Number::Float(-0.25)),
+                    Some(Number::Float(1e10)),
+                    Some(Number::Float(2.5e-3)),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None]);
+}
+
+#[test]
+fn display_round_trip() {
+    let ::Result { roots, diagnostics, .. } = ::simple_parse("(a  [b]\n {c} \"x\ty\n\")", &[], None);
+    assert!(diagnostics.is_empty());
+    let formatted = roots[0].to_string();
+    assert_eq!(formatted, "(a [b] {c} \"x\\ty\\n\")");
+
+    let ::Result { roots: reparsed, diagnostics, .. } = ::simple_parse(formatted.as_str(), &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(reparsed[0].to_string(), formatted);
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use tendril::StrTendril;
+
+use super::{push_children, Sexpr};
+use parse::Span;
+use token::{ListType, TokenInfo, quote_string, unescape_string};
+
+/// Options for `pretty_print`.
+#[derive(Clone, Debug)]
+pub struct PrettyOptions {
+    /// Lists that would make a line longer than this are split across lines.
+    pub max_width: usize,
+    /// How many spaces nested lines are indented past their list's opening delimiter.
+    pub indent: usize,
+    /// When true, a split list keeps its first argument on the same line as
+    /// its head, and lines up the remaining arguments underneath it:
+    ///
+    /// ```text
+    /// (define (foo x)
+    ///         (bar x))
+    /// ```
+    pub align_heads: bool,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> PrettyOptions {
+        PrettyOptions {
+            max_width: 80,
+            indent: 2,
+            align_heads: false,
+        }
+    }
+}
+
+impl PrettyOptions {
+    pub fn new() -> PrettyOptions {
+        PrettyOptions::default()
+    }
+
+    pub fn with_max_width(mut self, max_width: usize) -> PrettyOptions {
+        self.max_width = max_width;
+        self
+    }
+
+    pub fn with_indent(mut self, indent: usize) -> PrettyOptions {
+        self.indent = indent;
+        self
+    }
+
+    pub fn with_align_heads(mut self, align_heads: bool) -> PrettyOptions {
+        self.align_heads = align_heads;
+        self
+    }
+}
+
+/// Formats `sexpr` as source text, splitting lists that don't fit within
+/// `opts.max_width` across multiple lines.
+///
+/// Lists that fit are written on one line, exactly as `Display` would write them.
+pub fn pretty_print(sexpr: &Sexpr, opts: &PrettyOptions) -> String {
+    enum Item<'a> {
+        // An expression to write with the cursor at the given column
+        Node(&'a Sexpr, usize),
+        Close(ListType),
+        Newline(usize),
+        Space,
+    }
+
+    let widths = flat_widths(sexpr);
+    let mut out = String::new();
+    let mut stack = vec![Item::Node(sexpr, 0)];
+    while let Some(item) = stack.pop() {
+        let (sexpr, column) = match item {
+            Item::Node(sexpr, column) => (sexpr, column),
+            Item::Close(list_type) => {
+                out.push_str(&list_type.to_string(false));
+                continue;
+            }
+            Item::Newline(column) => {
+                newline(column, &mut out);
+                continue;
+            }
+            Item::Space => {
+                out.push(' ');
+                continue;
+            }
+        };
+        if column + widths[&(sexpr as *const Sexpr)] <= opts.max_width {
+            out.push_str(&sexpr.to_string());
+            continue;
+        }
+
+        match sexpr {
+            &Sexpr::List { list_type, ref children, .. } if !children.is_empty() => {
+                out.push_str(&list_type.to_string(true));
+                stack.push(Item::Close(list_type));
+                let column = column + 1;
+                let head = &children[0];
+
+                let head_is_list = head.kind() == ::parse::SexprKind::List;
+                if opts.align_heads && !head_is_list && children.len() > 1 {
+                    // Keep the first argument on the head's line and align the rest with it
+                    let arg_column = column + widths[&(head as *const Sexpr)] + 1;
+                    for child in children[2..].iter().rev() {
+                        stack.push(Item::Node(child, arg_column));
+                        stack.push(Item::Newline(arg_column));
+                    }
+                    stack.push(Item::Node(&children[1], arg_column));
+                    stack.push(Item::Space);
+                } else {
+                    let child_column = column - 1 + opts.indent;
+                    for child in children[1..].iter().rev() {
+                        stack.push(Item::Node(child, child_column));
+                        stack.push(Item::Newline(child_column));
+                    }
+                }
+                stack.push(Item::Node(head, column));
+            }
+            &Sexpr::UnaryOperator { ref op, ref child, ref span } => {
+                let op_text = token_text(op, span);
+                out.push_str(op_text.as_ref());
+                stack.push(Item::Node(child, column + width(op_text.as_ref())));
+            }
+            &Sexpr::WithMeta { ref caret, ref meta, ref expr, ref span } => {
+                let caret_text = token_text(caret, span);
+                out.push_str(caret_text.as_ref());
+                stack.push(Item::Node(expr, column));
+                stack.push(Item::Newline(column));
+                stack.push(Item::Node(meta, column + width(caret_text.as_ref())));
+            }
+            _ => out.push_str(&sexpr.to_string()),
+        }
+    }
+    out
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn token_text(token: &TokenInfo, span: &Span) -> StrTendril {
+    span.full_text.subtendril(token.byte_offset as u32, token.length)
+}
+
+fn newline(column: usize, out: &mut String) {
+    out.push('\n');
+    for _ in 0..column {
+        out.push(' ');
+    }
+}
+
+// Measures how wide every expression in the tree is when written on one line,
+// keyed by address.  Children are measured before their parents, so each
+// expression is only looked at once.
+fn flat_widths(root: &Sexpr) -> HashMap<*const Sexpr, usize> {
+    let mut widths = HashMap::new();
+    // Each expression is pushed once to queue its children and once more to
+    // be measured after them
+    let mut stack = vec![(root, false)];
+    while let Some((sexpr, children_done)) = stack.pop() {
+        if !children_done {
+            stack.push((sexpr, true));
+            let mut children = vec![];
+            push_children(sexpr, &mut children);
+            stack.extend(children.into_iter().map(|child| (child, false)));
+            continue;
+        }
+
+        let width_of = |child: &Sexpr| widths[&(child as *const Sexpr)];
+        let total = match sexpr {
+            &Sexpr::List { list_type, ref children, .. } => {
+                let delimiters = width(&list_type.to_string(true)) +
+                                 width(&list_type.to_string(false));
+                delimiters + children.iter().map(&width_of).sum::<usize>() +
+                children.len().saturating_sub(1)
+            }
+            &Sexpr::UnaryOperator { ref op, ref child, ref span } => {
+                width(token_text(op, span).as_ref()) + width_of(child)
+            }
+            &Sexpr::WithMeta { ref caret, ref meta, ref expr, ref span } => {
+                width(token_text(caret, span).as_ref()) + width_of(meta) + 1 + width_of(expr)
+            }
+            &Sexpr::String(_, ref span) => {
+                width(&quote_string(&unescape_string(span.text().as_ref())))
+            }
+            &Sexpr::Terminal(_, ref span) => width(span.text().as_ref()),
+        };
+        widths.insert(sexpr as *const Sexpr, total);
+    }
+    widths
+}
+
+#[test]
+fn pretty_print_test() {
+    let source = "(define (foo x y) (if (bar x) (baz y) (qux x y)))";
+    let ::Result { roots, .. } = ::simple_parse(source, &[], None);
+
+    assert_eq!(pretty_print(&roots[0], &PrettyOptions::new()), source);
+
+    let narrow = PrettyOptions::new().with_max_width(30);
+    assert_eq!(pretty_print(&roots[0], &narrow),
+               "(define
+  (foo x y)
+  (if
+    (bar x)
+    (baz y)
+    (qux x y)))");
+
+    let four = narrow.clone().with_indent(4);
+    assert_eq!(pretty_print(&roots[0], &four),
+               "(define
+    (foo x y)
+    (if
+        (bar x)
+        (baz y)
+        (qux x y)))");
+
+    let aligned = narrow.with_align_heads(true);
+    assert_eq!(pretty_print(&roots[0], &aligned),
+               "(define (foo x y)
+        (if (bar x)
+            (baz y)
+            (qux x y)))");
+
+    let source: StrTendril = "^{:doc x} (foo bar)".into();
+    let tokens = ::token::tokenize(source.clone(), &["^"]);
+    let options = ::parse::ParseOptions::new().with_metadata(true);
+    let ::Result { roots, .. } = ::parse::parse_with_options(&source, tokens, None, &options);
+    assert_eq!(pretty_print(&roots[0], &PrettyOptions::new().with_max_width(10)),
+               "^{:doc x}\n(foo bar)");
+}
+
+#[test]
+fn pretty_print_deep() {
+    let depth = 100000;
+    let source = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+    let ::Result { roots, diagnostics, .. } = ::simple_parse(source.as_str(), &[], None);
+    assert!(diagnostics.is_empty());
+    assert_eq!(pretty_print(&roots[0], &PrettyOptions::new()), source);
+}
@@ -0,0 +1,83 @@
+use std::rc::Rc;
+
+use super::Sexpr;
+use parse::Span;
+use token::{self, ListType, TokenInfo, TokenType};
+use tendril::StrTendril;
+
+/// Builders for the trees that parsing `source` produces, for writing
+/// expected values in tests without spelling out every `TokenInfo` and
+/// `Span` by hand.
+///
+/// Each builder takes the whole source text and the byte offset where the
+/// expression starts, tokenizes the source with no splitters, and copies the
+/// positions from the token found there.  They panic if no suitable token
+/// starts at that offset.
+impl Sexpr {
+    /// Builds the terminal that starts at `byte_offset` in `source`.
+    pub fn terminal_for_test(source: &str, byte_offset: usize) -> Sexpr {
+        let (source, token) = token_at(source, byte_offset, TokenType::Atom);
+        Sexpr::Terminal(token, Span::from_token(&token, &source, &None))
+    }
+
+    /// Builds the string literal that starts at `byte_offset` in `source`.
+    pub fn string_for_test(source: &str, byte_offset: usize) -> Sexpr {
+        let (source, token) = token_at(source, byte_offset, TokenType::String);
+        Sexpr::String(token, Span::from_token(&token, &source, &None))
+    }
+
+    /// Builds the list whose opening delimiter is at `byte_offset` in
+    /// `source`, holding `children`.
+    pub fn list_for_test(source: &str, byte_offset: usize, children: Vec<Sexpr>) -> Sexpr {
+        let source: StrTendril = source.into();
+        let mut depth = 0;
+        let mut opening: Option<TokenInfo> = None;
+        for token in token::tokenize(source.clone(), &[]).filter_map(|t| t.ok()) {
+            match (token.typ, opening) {
+                (TokenType::ListOpening(_), None) if token.byte_offset == byte_offset => {
+                    opening = Some(token);
+                }
+                (TokenType::ListOpening(_), Some(_)) => depth += 1,
+                (TokenType::ListClosing(_), Some(open)) if depth == 0 => {
+                    let list_type = match open.typ {
+                        TokenType::ListOpening(list_type) => list_type,
+                        _ => unreachable!(),
+                    };
+                    return list(&source, list_type, open, token, children);
+                }
+                (TokenType::ListClosing(_), Some(_)) => depth -= 1,
+                _ => {}
+            }
+        }
+        panic!("no closed list starts at byte {} of {:?}", byte_offset, source);
+    }
+}
+
+fn list(source: &StrTendril,
+        list_type: ListType,
+        opening_token: TokenInfo,
+        closing_token: TokenInfo,
+        children: Vec<Sexpr>)
+        -> Sexpr {
+    let file: Option<Rc<String>> = None;
+    let span = Span::from_spans(&Span::from_token(&opening_token, source, &file),
+                                &Span::from_token(&closing_token, source, &file));
+    Sexpr::List {
+        list_type: list_type,
+        opening_token: opening_token,
+        closing_token: closing_token,
+        children: children,
+        span: span,
+    }
+}
+
+fn token_at(source: &str, byte_offset: usize, typ: TokenType) -> (StrTendril, TokenInfo) {
+    let source: StrTendril = source.into();
+    let token = token::tokenize(source.clone(), &[])
+        .filter_map(|t| t.ok())
+        .find(|t| t.byte_offset == byte_offset && t.typ == typ);
+    match token {
+        Some(token) => (source, token),
+        None => panic!("no {:?} token starts at byte {} of {:?}", typ, byte_offset, source),
+    }
+}
@@ -0,0 +1,100 @@
+use super::Sexpr;
+use parse::Span;
+
+/// Returned by the `SexprVisitor` callbacks for expressions with children.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Visit {
+    /// Go on to visit the expression's children.
+    Continue,
+    /// Skip the expression's children.
+    Prune,
+}
+
+/// Walks an `Sexpr` tree, calling back for each expression in pre-order.
+///
+/// Every callback receives the expression and its span, and defaults to
+/// doing nothing, so an implementation only needs to override the variants
+/// it cares about.  `walk` drives the traversal.
+pub trait SexprVisitor {
+    fn visit_list(&mut self, _sexpr: &Sexpr, _span: &Span) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_unary_operator(&mut self, _sexpr: &Sexpr, _span: &Span) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_with_meta(&mut self, _sexpr: &Sexpr, _span: &Span) -> Visit {
+        Visit::Continue
+    }
+
+    fn visit_terminal(&mut self, _sexpr: &Sexpr, _span: &Span) {}
+
+    fn visit_string(&mut self, _sexpr: &Sexpr, _span: &Span) {}
+
+    /// Visits `sexpr`, and then its children unless the callback prunes them.
+    fn walk(&mut self, sexpr: &Sexpr) {
+        match sexpr {
+            &Sexpr::List { ref children, ref span, .. } => {
+                if self.visit_list(sexpr, span) == Visit::Continue {
+                    for child in children {
+                        self.walk(child);
+                    }
+                }
+            }
+            &Sexpr::UnaryOperator { ref child, ref span, .. } => {
+                if self.visit_unary_operator(sexpr, span) == Visit::Continue {
+                    self.walk(child);
+                }
+            }
+            &Sexpr::WithMeta { ref meta, ref expr, ref span, .. } => {
+                if self.visit_with_meta(sexpr, span) == Visit::Continue {
+                    self.walk(meta);
+                    self.walk(expr);
+                }
+            }
+            &Sexpr::Terminal(_, ref span) => self.visit_terminal(sexpr, span),
+            &Sexpr::String(_, ref span) => self.visit_string(sexpr, span),
+        }
+    }
+}
+
+#[test]
+fn symbol_table() {
+    // Collects the names bound by `(def name value)`, ignoring quoted forms
+    struct Definitions {
+        names: Vec<(String, String)>,
+        strings: usize,
+    }
+
+    impl SexprVisitor for Definitions {
+        fn visit_list(&mut self, sexpr: &Sexpr, span: &Span) -> Visit {
+            match sexpr.head_symbol() {
+                Some(ref head) if head.as_ref() == "quote" => Visit::Prune,
+                Some(ref head) if head.as_ref() == "def" => {
+                    if let &Sexpr::List { ref children, .. } = sexpr {
+                        if let Some(name) = children.get(1) {
+                            self.names.push((name.text().to_string(), span.location_string()));
+                        }
+                    }
+                    Visit::Continue
+                }
+                _ => Visit::Continue,
+            }
+        }
+
+        fn visit_string(&mut self, _sexpr: &Sexpr, _span: &Span) {
+            self.strings += 1;
+        }
+    }
+
+    let ::Result { roots, .. } =
+        ::simple_parse("(def a 1)\n(quote (def b 2) \"s\")\n(do (def c \"s\"))", &[], None);
+    let mut definitions = Definitions { names: vec![], strings: 0 };
+    for root in &roots {
+        definitions.walk(root);
+    }
+    assert_eq!(definitions.names,
+               vec![("a".to_string(), "1:1".to_string()), ("c".to_string(), "3:5".to_string())]);
+    assert_eq!(definitions.strings, 1);
+}
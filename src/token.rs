@@ -1,7 +1,10 @@
-use super::parse::Span;
+use std::rc::Rc;
+use std::io::{ErrorKind, Read};
+
+use super::parse::{Span, StartEnd};
 use tendril::StrTendril;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum ListType {
     Paren, // ( and )
     Bracket, // [ and ]
@@ -13,6 +16,8 @@ pub enum TokenType {
     ListOpening(ListType),
     ListClosing(ListType),
     Whitespace,
+    Comment,
+    UnaryOperator,
     String,
     Atom,
 }
@@ -22,6 +27,12 @@ pub type TokResult<OK> = Result<OK, TokError>;
 #[derive(Debug, Eq, PartialEq)]
 pub enum TokError {
     UnclosedString(Span),
+    /// An atom longer than `TokenizeOptions::max_atom_length`.  The span
+    /// covers the whole atom, but the token that was emitted is truncated.
+    AtomTooLong { span: Span, max: usize },
+    /// Reading the input failed, or it was not valid UTF-8.  Only produced
+    /// by `tokenize_reader`.
+    Io(String),
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -33,12 +44,82 @@ pub struct TokenInfo {
     pub typ: TokenType,
 }
 
+/// Options that change how text is split into tokens.
+#[derive(Clone, Debug)]
+pub struct TokenizeOptions {
+    /// When true, list delimiters are skipped instead of being emitted as
+    /// tokens, producing a flat stream of atoms, strings and whitespace.
+    pub ignore_brackets: bool,
+    /// The string that starts a comment running to the end of the line,
+    /// or `None` to disable comments.  Defaults to `;`.
+    pub comment_start: Option<String>,
+    /// When true (the default), a comment only starts at the beginning of a
+    /// token, so `a;b` is a single atom.  When false, the comment start ends
+    /// an atom wherever it appears, so `a;b` is the atom `a` and the comment `;b`.
+    pub comments_only_at_token_start: bool,
+    /// Prefix operators, like `'` or `,@`, that are emitted as
+    /// `TokenType::UnaryOperator` when they appear at the start of a token.
+    /// When several match, the longest one wins.
+    pub unary_operators: Vec<String>,
+    /// The longest atom, in characters, that is emitted whole.  Longer atoms
+    /// are truncated to this length and reported as `TokError::AtomTooLong`.
+    /// Unlimited by default.
+    pub max_atom_length: Option<usize>,
+}
+
+impl Default for TokenizeOptions {
+    fn default() -> TokenizeOptions {
+        TokenizeOptions {
+            ignore_brackets: false,
+            comment_start: Some(";".into()),
+            comments_only_at_token_start: true,
+            unary_operators: vec![],
+            max_atom_length: None,
+        }
+    }
+}
+
+impl TokenizeOptions {
+    pub fn new() -> TokenizeOptions {
+        TokenizeOptions::default()
+    }
+
+    pub fn with_ignore_brackets(mut self, ignore_brackets: bool) -> TokenizeOptions {
+        self.ignore_brackets = ignore_brackets;
+        self
+    }
+
+    pub fn with_comment_start(mut self, comment_start: Option<&str>) -> TokenizeOptions {
+        self.comment_start = comment_start.map(String::from);
+        self
+    }
+
+    pub fn with_comments_only_at_token_start(mut self, only_at_start: bool) -> TokenizeOptions {
+        self.comments_only_at_token_start = only_at_start;
+        self
+    }
+
+    pub fn with_unary_operators(mut self, operators: &[&str]) -> TokenizeOptions {
+        self.unary_operators = operators.iter().map(|&o| String::from(o)).collect();
+        self
+    }
+
+    pub fn with_max_atom_length(mut self, max: usize) -> TokenizeOptions {
+        self.max_atom_length = Some(max);
+        self
+    }
+}
+
 pub struct TokenIterator<'a> {
     splitters: &'a [&'a str],
+    options: TokenizeOptions,
+    source: StrTendril,
     remaining: StrTendril,
     line_number: usize,
     column_number: usize,
     byte_offset: usize,
+    // An error to report after the token that was just returned
+    pending: Option<TokError>,
 }
 
 impl ListType {
@@ -55,37 +136,90 @@ impl ListType {
     }
 }
 
+impl TokenType {
+    /// Returns a stable name for this kind of token, suitable for mapping
+    /// onto syntax-highlighting scopes.
+    pub fn kind_name(&self) -> &'static str {
+        match *self {
+            TokenType::ListOpening(_) |
+            TokenType::ListClosing(_) => "punctuation",
+            TokenType::Whitespace => "whitespace",
+            TokenType::Comment => "comment",
+            TokenType::UnaryOperator => "operator",
+            TokenType::String => "string",
+            TokenType::Atom => "atom",
+        }
+    }
+}
+
 impl<'a> Iterator for TokenIterator<'a> {
     type Item = TokResult<TokenInfo>;
 
     fn next(&mut self) -> Option<TokResult<TokenInfo>> {
-        match next_token(&self.remaining, self.splitters) {
+        if let Some(error) = self.pending.take() {
+            return Some(Err(error));
+        }
+        loop {
+            let r = self.next_any();
+            match r {
+                Some(Ok(TokenInfo { typ: TokenType::ListOpening(_), .. })) |
+                Some(Ok(TokenInfo { typ: TokenType::ListClosing(_), .. }))
+                    if self.options.ignore_brackets => continue,
+                r => return r,
+            }
+        }
+    }
+}
+
+impl<'a> TokenIterator<'a> {
+    // Moves the line/column/byte position past `text`.  Any token (not just
+    // whitespace) may contain newlines, e.g. a string literal spanning lines.
+    fn advance_position(&mut self, text: &str) {
+        advance_position(text,
+                         &mut self.line_number,
+                         &mut self.column_number,
+                         &mut self.byte_offset);
+    }
+
+    fn next_any(&mut self) -> Option<TokResult<TokenInfo>> {
+        match next_token(self.remaining.as_ref(), self.splitters, &self.options) {
             None => None,
-            Some(Err(e)) => Some(Err(e)),
-            Some(Ok((typ, s))) => {
-                let r = Some(Ok(TokenInfo {
-                                    line_number: self.line_number,
-                                    column_number: self.column_number,
-                                    byte_offset: self.byte_offset,
-                                    typ: typ,
-                                    length: s.len32(),
-                                }));
-
-                if let TokenType::Whitespace = typ {
-                    for chr in s.as_bytes().iter() {
-                        if *chr ==  b'\n' {
-                            self.line_number += 1;
-                            self.column_number = 1;
-                        } else {
-                            self.column_number += 1;
-                        }
+            Some(Err(len)) => {
+                let rest = self.remaining.subtendril(0, len as u32);
+                // An unclosed string swallows the rest of the input
+                let token = TokenInfo {
+                    line_number: self.line_number,
+                    column_number: self.column_number,
+                    byte_offset: self.byte_offset,
+                    typ: TokenType::String,
+                    length: rest.len32(),
+                };
+                let span = Span::from_token(&token, &self.source, &None);
+                self.advance_position(rest.as_ref());
+                self.remaining = StrTendril::new();
+                Some(Err(TokError::UnclosedString(span)))
+            }
+            Some(Ok((typ, len))) => {
+                let s = self.remaining.subtendril(0, len as u32);
+                let mut token = TokenInfo {
+                    line_number: self.line_number,
+                    column_number: self.column_number,
+                    byte_offset: self.byte_offset,
+                    typ: typ,
+                    length: s.len32(),
+                };
+                if let (TokenType::Atom, Some(max)) = (typ, self.options.max_atom_length) {
+                    if let Some((truncated, _)) = s.as_ref().char_indices().nth(max) {
+                        let span = Span::from_token(&token, &self.source, &None);
+                        self.pending = Some(TokError::AtomTooLong { span: span, max: max });
+                        token.length = truncated as u32;
                     }
-                } else {
-                    self.column_number += s.len();
                 }
+                let r = Some(Ok(token));
+
+                self.advance_position(s.as_ref());
 
                 let bytes_consumed = s.len();
-                self.byte_offset += bytes_consumed;
 
                 // TODO: is this wrong?
                 let bytes_consumed = bytes_consumed as u32;
@@ -99,10 +233,43 @@ impl<'a> Iterator for TokenIterator<'a> {
     }
 }
 
+fn advance_position(text: &str,
+                    line_number: &mut usize,
+                    column_number: &mut usize,
+                    byte_offset: &mut usize) {
+    for chr in text.as_bytes().iter() {
+        if *chr == b'\n' {
+            *line_number += 1;
+            *column_number = 1;
+        } else {
+            *column_number += 1;
+        }
+    }
+    *byte_offset += text.len();
+}
+
+// Returns the length of the string literal at the start of `s` (including
+// both quotes), or `None` if the closing quote is missing.
+fn string_literal_len(s: &[u8]) -> Option<usize> {
+    let mut i = 1;
+    while i < s.len() {
+        match s[i] {
+            b'"' => return Some(i + 1),
+            // Skip whatever is escaped, so that `\"` doesn't end the string
+            b'\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// Produces the type and byte length of the next token.  An unclosed string
+// literal is reported as `Err` holding the length of the rest of the input.
 // TODO: this is slow, replace it
-fn next_token(string: &StrTendril,
-              splitters: &[&str])
-              -> Option<TokResult<(TokenType, StrTendril)>> {
+fn next_token(string: &str,
+              splitters: &[&str],
+              options: &TokenizeOptions)
+              -> Option<Result<(TokenType, usize), usize>> {
     fn idx_until<F>(s: &[u8], f: F) -> Option<usize>
         where F: Fn(u8) -> bool
     {
@@ -123,18 +290,41 @@ fn next_token(string: &StrTendril,
         None => return None,
     };
 
+    if let Some(ref comment_start) = options.comment_start {
+        if !comment_start.is_empty() && string.starts_with(comment_start.as_str()) {
+            // The comment runs up to, but not including, the next newline
+            let len = string.find('\n').unwrap_or(string.len());
+            return Some(Ok((TokenType::Comment, len)));
+        }
+    }
+
+    let operator_len = options.unary_operators
+        .iter()
+        .filter(|op| !op.is_empty() && string.starts_with(op.as_str()))
+        .map(|op| op.len())
+        .max();
+    if let Some(len) = operator_len {
+        return Some(Ok((TokenType::UnaryOperator, len)));
+    }
+
     let next = match first {
         b if is_whitespace(b) => {
             let last_idx = idx_until(string.as_bytes(), is_whitespace).unwrap();
-            Some(Ok((TokenType::Whitespace, string.subtendril(0, last_idx as u32))))
+            Some(Ok((TokenType::Whitespace, last_idx)))
         }
 
-        b'(' => Some(Ok((TokenType::ListOpening(ListType::Paren), string.subtendril(0, 1)))),
-        b'{' => Some(Ok((TokenType::ListOpening(ListType::Brace), string.subtendril(0, 1)))),
-        b'[' => Some(Ok((TokenType::ListOpening(ListType::Bracket), string.subtendril(0, 1)))),
-        b')' => Some(Ok((TokenType::ListClosing(ListType::Paren), string.subtendril(0, 1)))),
-        b'}' => Some(Ok((TokenType::ListClosing(ListType::Brace), string.subtendril(0, 1)))),
-        b']' => Some(Ok((TokenType::ListClosing(ListType::Bracket), string.subtendril(0, 1)))),
+        b'(' => Some(Ok((TokenType::ListOpening(ListType::Paren), 1))),
+        b'{' => Some(Ok((TokenType::ListOpening(ListType::Brace), 1))),
+        b'[' => Some(Ok((TokenType::ListOpening(ListType::Bracket), 1))),
+        b')' => Some(Ok((TokenType::ListClosing(ListType::Paren), 1))),
+        b'}' => Some(Ok((TokenType::ListClosing(ListType::Brace), 1))),
+        b']' => Some(Ok((TokenType::ListClosing(ListType::Bracket), 1))),
+        b'"' => {
+            match string_literal_len(string.as_bytes()) {
+                Some(len) => Some(Ok((TokenType::String, len))),
+                None => Some(Err(string.len())),
+            }
+        }
         _ => {
             let last_idx = idx_until(string.as_bytes(), |b| match b {
                 b'(' | b'{' | b'[' | b')' | b'}' | b']' => false,
@@ -142,12 +332,12 @@ fn next_token(string: &StrTendril,
                 _ => true,
             })
                     .unwrap();
-            let mut substr = string.subtendril(0, last_idx as u32);
+            let mut substr = &string[..last_idx];
             let mut lowest = None;
             for splitter in splitters {
-                lowest = match (lowest, substr.as_ref().find(splitter)) {
+                lowest = match (lowest, substr.find(splitter)) {
                     (_, Some(0)) => {
-                        substr = string.subtendril(0, splitter.len() as u32);
+                        substr = &string[..splitter.len()];
                         lowest = None;
                         break;
                     }
@@ -159,26 +349,370 @@ fn next_token(string: &StrTendril,
             }
 
             if let Some(new_low) = lowest {
-                substr = string.subtendril(0, new_low as u32);
+                substr = &string[..new_low];
             }
 
-            Some(Ok((TokenType::Atom, substr)))
+            if !options.comments_only_at_token_start {
+                if let Some(ref comment_start) = options.comment_start {
+                    if let Some(idx) = substr.find(comment_start.as_str()) {
+                        substr = &string[..idx];
+                    }
+                }
+            }
+
+            Some(Ok((TokenType::Atom, substr.len())))
         }
     };
     return next;
 }
 
+/// Takes a string literal, quotes included, and returns its contents with
+/// `\"`, `\\`, `\n` and `\t` escapes replaced by the characters they stand for.
+///
+/// Several literals separated by whitespace, as merged by
+/// `ParseOptions::concat_strings`, are unescaped and joined together.
+///
+/// Unrecognized escapes are kept as written.
+pub fn unescape_string(literal: &str) -> String {
+    if let Some(bodies) = split_literals(literal) {
+        if bodies.len() > 1 {
+            return bodies.into_iter().map(unescape_body).collect();
+        }
+    }
+
+    let body = if literal.len() >= 2 && literal.starts_with('"') && literal.ends_with('"') {
+        &literal[1..literal.len() - 1]
+    } else {
+        literal
+    };
+    unescape_body(body)
+}
+
+// Splits a run of complete string literals separated by whitespace into the
+// text between each one's quotes.  Returns `None` for anything else.
+fn split_literals(text: &str) -> Option<Vec<&str>> {
+    let mut bodies = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let len = match string_literal_len(rest.as_bytes()) {
+            Some(len) if rest.starts_with('"') => len,
+            _ => return None,
+        };
+        bodies.push(&rest[1..len - 1]);
+        rest = rest[len..].trim_start();
+    }
+    Some(bodies)
+}
+
+fn unescape_body(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Wraps `contents` in double quotes, escaping it so that `unescape_string`
+/// gives it back unchanged.
+pub fn quote_string(contents: &str) -> String {
+    let mut out = String::with_capacity(contents.len() + 2);
+    out.push('"');
+    for c in contents.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
 
 pub fn tokenize<'a>(string: StrTendril, seps: &'a [&'a str]) -> TokenIterator {
+    tokenize_with_options(string, seps, TokenizeOptions::default())
+}
+
+/// Tokenizes a string like `tokenize`, with non-default `TokenizeOptions`.
+pub fn tokenize_with_options<'a>(string: StrTendril,
+                                 seps: &'a [&'a str],
+                                 options: TokenizeOptions)
+                                 -> TokenIterator<'a> {
     TokenIterator {
         splitters: seps,
+        options: options,
+        source: string.clone(),
         remaining: string,
         line_number: 1,
         column_number: 1,
         byte_offset: 0,
+        pending: None,
+    }
+}
+
+/// Tokenizes a string, attaching the `Span` that each token covers.
+pub fn tokenize_with_spans<S: Into<StrTendril>>(string: S,
+                                                splitters: &[&str],
+                                                file: Option<&str>)
+                                                -> Vec<TokResult<(TokenInfo, Span)>> {
+    let string = string.into();
+    let file = file.map(|f| Rc::new(f.to_string()));
+    tokenize(string.clone(), splitters)
+        .map(|token| token.map(|t| (t, Span::from_token(&t, &string, &file))))
+        .collect()
+}
+
+/// A token that borrows its text from the input, produced by `tokenize_str`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TokenSlice<'a> {
+    pub text: &'a str,
+    pub line_number: usize,
+    pub column_number: usize,
+    pub byte_offset: usize,
+    pub typ: TokenType,
+}
+
+impl<'a> TokenSlice<'a> {
+    /// Returns the `TokenInfo` that `tokenize` would have produced for this token.
+    pub fn info(&self) -> TokenInfo {
+        TokenInfo {
+            line_number: self.line_number,
+            column_number: self.column_number,
+            byte_offset: self.byte_offset,
+            length: self.text.len() as u32,
+            typ: self.typ,
+        }
+    }
+}
+
+/// An iterator over the tokens of a `&str`, created by `tokenize_str`.
+pub struct StrTokenIterator<'a> {
+    splitters: &'a [&'a str],
+    options: TokenizeOptions,
+    source: &'a str,
+    line_number: usize,
+    column_number: usize,
+    byte_offset: usize,
+}
+
+impl<'a> Iterator for StrTokenIterator<'a> {
+    type Item = TokResult<TokenSlice<'a>>;
+
+    fn next(&mut self) -> Option<TokResult<TokenSlice<'a>>> {
+        let remaining = &self.source[self.byte_offset..];
+        let (typ, len) = match next_token(remaining, self.splitters, &self.options) {
+            None => return None,
+            Some(Ok(token)) => token,
+            Some(Err(len)) => {
+                // Only the error path needs a tendril, to build the span
+                let token = TokenInfo {
+                    line_number: self.line_number,
+                    column_number: self.column_number,
+                    byte_offset: self.byte_offset,
+                    typ: TokenType::String,
+                    length: len as u32,
+                };
+                let span = Span::from_token(&token, &self.source.into(), &None);
+                self.byte_offset = self.source.len();
+                return Some(Err(TokError::UnclosedString(span)));
+            }
+        };
+
+        let slice = TokenSlice {
+            text: &remaining[..len],
+            line_number: self.line_number,
+            column_number: self.column_number,
+            byte_offset: self.byte_offset,
+            typ: typ,
+        };
+        advance_position(slice.text,
+                         &mut self.line_number,
+                         &mut self.column_number,
+                         &mut self.byte_offset);
+        Some(Ok(slice))
+    }
+}
+
+/// Tokenizes a string like `tokenize`, but without copying it into a tendril.
+///
+/// Each token borrows its text from `string`, which makes this cheaper for
+/// read-only passes like syntax highlighting.
+pub fn tokenize_str<'a>(string: &'a str, splitters: &'a [&'a str]) -> StrTokenIterator<'a> {
+    StrTokenIterator {
+        splitters: splitters,
+        options: TokenizeOptions::default(),
+        source: string,
+        line_number: 1,
+        column_number: 1,
+        byte_offset: 0,
+    }
+}
+
+/// An iterator over the tokens of a reader, created by `tokenize_reader`.
+pub struct ReaderTokenIterator<'a, R> {
+    reader: R,
+    splitters: &'a [&'a str],
+    options: TokenizeOptions,
+    // The text that has been read, from the start of the next token onwards
+    // once `consumed` is dropped
+    buffer: String,
+    // How much of `buffer` has already been emitted as tokens; it is only
+    // dropped when more input is read, rather than after every token
+    consumed: usize,
+    // The start of a UTF-8 sequence that was split across two reads
+    partial: Vec<u8>,
+    eof: bool,
+    done: bool,
+    line_number: usize,
+    column_number: usize,
+    byte_offset: usize,
+}
+
+impl<'a, R: Read> ReaderTokenIterator<'a, R> {
+    // Reads another chunk of input onto the end of the buffer.
+    fn fill(&mut self) -> Result<(), String> {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+
+        let mut chunk = [0; 8192];
+        let read = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(read) => break read,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+
+        if read == 0 {
+            self.eof = true;
+            if !self.partial.is_empty() {
+                return Err("stream did not contain valid UTF-8".into());
+            }
+            return Ok(());
+        }
+
+        self.partial.extend_from_slice(&chunk[..read]);
+        let valid = match ::std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            // The input ends partway through a character; wait for the rest of it
+            Err(ref e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err("stream did not contain valid UTF-8".into()),
+        };
+        self.buffer.push_str(::std::str::from_utf8(&self.partial[..valid]).unwrap());
+        self.partial.drain(..valid);
+        Ok(())
+    }
+}
+
+impl<'a, R: Read> Iterator for ReaderTokenIterator<'a, R> {
+    type Item = TokResult<TokenInfo>;
+
+    fn next(&mut self) -> Option<TokResult<TokenInfo>> {
+        while !self.done {
+            let pending = &self.buffer[self.consumed..];
+            // A token that runs up to the end of the buffer might continue in
+            // the next read, so it is only emitted once more input has arrived.
+            match next_token(pending, self.splitters, &self.options) {
+                Some(Ok((typ, len))) if len < pending.len() || self.eof => {
+                    let token = TokenInfo {
+                        line_number: self.line_number,
+                        column_number: self.column_number,
+                        byte_offset: self.byte_offset,
+                        typ: typ,
+                        length: len as u32,
+                    };
+                    advance_position(&pending[..len],
+                                     &mut self.line_number,
+                                     &mut self.column_number,
+                                     &mut self.byte_offset);
+                    self.consumed += len;
+                    return Some(Ok(token));
+                }
+                Some(Err(len)) if self.eof => {
+                    // The text before the string has already been dropped, so
+                    // the span has no `full_text`, only its positions
+                    let (mut line, mut column, mut offset) =
+                        (self.line_number, self.column_number, self.byte_offset);
+                    advance_position(&pending[..len], &mut line, &mut column, &mut offset);
+                    let span = Span {
+                        text_bytes: StartEnd {
+                            start: self.byte_offset as u32,
+                            end: offset as u32,
+                        },
+                        lines_covered: StartEnd {
+                            start: self.line_number as u32,
+                            end: line as u32,
+                        },
+                        columns: StartEnd {
+                            start: self.column_number as u32,
+                            end: column as u32,
+                        },
+                        full_text: StrTendril::new(),
+                        file: None,
+                    };
+                    self.done = true;
+                    return Some(Err(TokError::UnclosedString(span)));
+                }
+                None if self.eof => self.done = true,
+                _ => {
+                    // Read until the unfinished token has at least doubled
+                    // before scanning it again, so that a long token is only
+                    // rescanned a logarithmic number of times
+                    let target = ::std::cmp::max(pending.len() * 2, 1);
+                    while !self.eof && self.buffer.len() - self.consumed < target {
+                        if let Err(message) = self.fill() {
+                            self.done = true;
+                            return Some(Err(TokError::Io(message)));
+                        }
+                    }
+                }
+            }
+        }
+        None
     }
 }
 
+/// Tokenizes the text read from `reader`, without reading it all into memory
+/// first.
+///
+/// The tokens are the same as `tokenize` would produce for the whole text,
+/// including their byte offsets, line numbers and columns.  Reading stops
+/// with a `TokError::Io` if the reader fails or produces invalid UTF-8.
+///
+/// The span of an unclosed string has the same positions as well, but its
+/// `full_text` is empty, since the text before the string has already been
+/// dropped; diagnostics for it are shown without a source snippet.
+pub fn tokenize_reader<'a, R: Read>(reader: R, splitters: &'a [&'a str]) -> ReaderTokenIterator<'a, R> {
+    ReaderTokenIterator {
+        reader: reader,
+        splitters: splitters,
+        options: TokenizeOptions::default(),
+        buffer: String::new(),
+        consumed: 0,
+        partial: vec![],
+        eof: false,
+        done: false,
+        line_number: 1,
+        column_number: 1,
+        byte_offset: 0,
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -461,4 +995,276 @@ mod test {
                             length: 1,
                         }]);
     }
+
+    #[test]
+    fn with_spans() {
+        let source = "(a b)";
+        let tokens = tokenize_with_spans(source, &[], Some("file"));
+        assert_eq!(tokens.len(), 5);
+        for token in tokens {
+            let (info, span) = token.unwrap();
+            let start = info.byte_offset;
+            let end = start + info.length as usize;
+            assert_eq!(span.text().as_ref(), &source[start..end]);
+            assert_eq!(span.file.as_ref().map(|f| f.as_str()), Some("file"));
+        }
+    }
+
+    #[test]
+    fn ignore_brackets() {
+        let options = TokenizeOptions::new().with_ignore_brackets(true);
+        let tokens: Vec<_> = tokenize_with_options("(a [b])".into(), &[], options)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tokens,
+                   vec![TokenInfo {
+                            line_number: 1,
+                            column_number: 2,
+                            byte_offset: 1,
+                            typ: TokenType::Atom,
+                            length: 1,
+                        },
+                        TokenInfo {
+                            line_number: 1,
+                            column_number: 3,
+                            byte_offset: 2,
+                            typ: TokenType::Whitespace,
+                            length: 1,
+                        },
+                        TokenInfo {
+                            line_number: 1,
+                            column_number: 5,
+                            byte_offset: 4,
+                            typ: TokenType::Atom,
+                            length: 1,
+                        }]);
+    }
+
+    #[test]
+    fn advance_over_multiline_token() {
+        let mut iter = tokenize("".into(), &[]);
+        iter.advance_position("\"first\nsecond\"");
+        assert_eq!((iter.line_number, iter.column_number, iter.byte_offset), (2, 8, 14));
+
+        iter.advance_position(" ");
+        assert_eq!((iter.line_number, iter.column_number, iter.byte_offset), (2, 9, 15));
+    }
+
+    #[test]
+    fn kind_names() {
+        assert_eq!(TokenType::ListOpening(ListType::Paren).kind_name(), "punctuation");
+        assert_eq!(TokenType::ListClosing(ListType::Brace).kind_name(), "punctuation");
+        assert_eq!(TokenType::Whitespace.kind_name(), "whitespace");
+        assert_eq!(TokenType::Comment.kind_name(), "comment");
+        assert_eq!(TokenType::UnaryOperator.kind_name(), "operator");
+        assert_eq!(TokenType::String.kind_name(), "string");
+        assert_eq!(TokenType::Atom.kind_name(), "atom");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(all_ok(r#""foo bar""#),
+                   vec![TokenInfo {
+                            line_number: 1,
+                            column_number: 1,
+                            byte_offset: 0,
+                            typ: TokenType::String,
+                            length: 9,
+                        }]);
+
+        let tokens = all_ok(r#"("a\"b\\" "\n\t" c)"#);
+        let types: Vec<_> = tokens.iter().map(|t| (t.typ, t.length)).collect();
+        assert_eq!(types,
+                   vec![(TokenType::ListOpening(ListType::Paren), 1),
+                        (TokenType::String, 8),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::String, 6),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::Atom, 1),
+                        (TokenType::ListClosing(ListType::Paren), 1)]);
+
+        let tokens = all_ok("\"a\nb\" c");
+        assert_eq!((tokens[2].line_number, tokens[2].column_number), (2, 4));
+    }
+
+    #[test]
+    fn unclosed_string() {
+        let tokens: Vec<_> = tokenize(r#"(a "bc\" d)"#.into(), &[]).collect();
+        assert_eq!(tokens.len(), 4);
+        match tokens[3] {
+            Err(TokError::UnclosedString(ref span)) => {
+                assert_eq!(span.text().as_ref(), r#""bc\" d)"#);
+                assert_eq!(span.columns.start, 4);
+            }
+            ref other => panic!("expected an unclosed string, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let tokens = all_ok("foo ; comment\nbar");
+        let types: Vec<_> = tokens.iter().map(|t| (t.typ, t.length)).collect();
+        assert_eq!(types,
+                   vec![(TokenType::Atom, 3),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::Comment, 9),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::Atom, 3)]);
+        assert_eq!((tokens[4].line_number, tokens[4].column_number), (2, 1));
+
+        // A comment at the end of the input has no newline to stop at
+        let tokens = all_ok("(a) ;; done");
+        assert_eq!(tokens.last().unwrap().typ, TokenType::Comment);
+        assert_eq!(tokens.last().unwrap().length, 7);
+
+        let options = TokenizeOptions::new().with_comment_start(Some("#"));
+        let tokens: Vec<_> = tokenize_with_options("a # b ; c".into(), &[], options)
+            .map(|t| t.unwrap().typ)
+            .collect();
+        assert_eq!(tokens,
+                   vec![TokenType::Atom, TokenType::Whitespace, TokenType::Comment]);
+
+        let options = TokenizeOptions::new().with_comment_start(None);
+        let tokens: Vec<_> = tokenize_with_options("; a".into(), &[], options)
+            .map(|t| t.unwrap().typ)
+            .collect();
+        assert_eq!(tokens,
+                   vec![TokenType::Atom, TokenType::Whitespace, TokenType::Atom]);
+    }
+
+    #[test]
+    fn unary_operators() {
+        let options = TokenizeOptions::new().with_unary_operators(&["'", "`", ",", ",@"]);
+        let tokens: Vec<_> = tokenize_with_options("'a ,@b ,c don't".into(), &[], options)
+            .map(|t| t.unwrap())
+            .map(|t| (t.typ, t.length))
+            .collect();
+        assert_eq!(tokens,
+                   vec![(TokenType::UnaryOperator, 1),
+                        (TokenType::Atom, 1),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::UnaryOperator, 2),
+                        (TokenType::Atom, 1),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::UnaryOperator, 1),
+                        (TokenType::Atom, 1),
+                        (TokenType::Whitespace, 1),
+                        (TokenType::Atom, 5)]);
+    }
+
+    #[test]
+    fn comments_mid_token() {
+        fn types(input: &str, options: TokenizeOptions) -> Vec<(TokenType, u32)> {
+            tokenize_with_options(input.into(), &[], options)
+                .map(|t| t.unwrap())
+                .map(|t| (t.typ, t.length))
+                .collect()
+        }
+
+        let default = TokenizeOptions::new();
+        assert_eq!(types("a;b", default.clone()), vec![(TokenType::Atom, 3)]);
+        assert_eq!(types("a ; comment", default),
+                   vec![(TokenType::Atom, 1), (TokenType::Whitespace, 1), (TokenType::Comment, 9)]);
+
+        let anywhere = TokenizeOptions::new().with_comments_only_at_token_start(false);
+        assert_eq!(types("a;b", anywhere.clone()),
+                   vec![(TokenType::Atom, 1), (TokenType::Comment, 2)]);
+        assert_eq!(types("a ; comment", anywhere),
+                   vec![(TokenType::Atom, 1), (TokenType::Whitespace, 1), (TokenType::Comment, 9)]);
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(unescape_string(r#""a\"b\\c\nd\te\q""#), "a\"b\\c\nd\te\\q");
+        assert_eq!(unescape_string(r#""""#), "");
+        assert_eq!(quote_string("a\"b\\c\nd\te"), r#""a\"b\\c\nd\te""#);
+
+        for contents in &["", "plain", "\"\\\n\t", "trailing \\"] {
+            assert_eq!(&unescape_string(&quote_string(contents)), contents);
+        }
+    }
+
+    #[test]
+    fn str_tokens_borrow_input() {
+        let input = "(foo \"a b\"\n  bar:baz) ; done";
+        let slices: Vec<_> = tokenize_str(input, &[":"]).map(|t| t.unwrap()).collect();
+        let infos: Vec<_> = tokenize(input.into(), &[":"]).map(|t| t.unwrap()).collect();
+        assert_eq!(slices.iter().map(TokenSlice::info).collect::<Vec<_>>(), infos);
+
+        let range = input.as_ptr() as usize..input.as_ptr() as usize + input.len();
+        for slice in &slices {
+            assert!(range.contains(&(slice.text.as_ptr() as usize)));
+            assert_eq!(slice.text, &input[slice.byte_offset..slice.byte_offset + slice.text.len()]);
+        }
+
+        let results: Vec<_> = tokenize_str("a \"b", &[]).collect();
+        match results[2] {
+            Err(TokError::UnclosedString(ref span)) => assert_eq!(span.text().as_ref(), "\"b"),
+            ref other => panic!("expected an unclosed string, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn max_atom_length() {
+        let options = TokenizeOptions::new().with_max_atom_length(4);
+        let source: StrTendril = "(abcdefgh xy)".into();
+        let tokens: Vec<_> = tokenize_with_options(source.clone(), &[], options.clone()).collect();
+        assert_eq!(tokens[1], Ok(TokenInfo { line_number: 1, column_number: 2, byte_offset: 1, length: 4, typ: TokenType::Atom }));
+        match tokens[2] {
+            Err(TokError::AtomTooLong { ref span, max: 4 }) => assert_eq!(span.text().as_ref(), "abcdefgh"),
+            ref other => panic!("expected an over-long atom, found {:?}", other),
+        }
+        assert_eq!(tokens[4], Ok(TokenInfo { line_number: 1, column_number: 11, byte_offset: 10, length: 2, typ: TokenType::Atom }));
+
+        let result = ::parse::parse(&source, tokenize_with_options(source.clone(), &[], options), None);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics.iter().next().unwrap().message, "atom exceeds 4 characters");
+        assert_eq!(result.roots[0].to_source_string(), "(abcd xy)");
+    }
+
+    #[test]
+    fn reader_matches_in_memory() {
+        // Hands out at most `size` bytes per read, splitting tokens and characters
+        struct Trickle<'a> {
+            bytes: &'a [u8],
+            size: usize,
+        }
+
+        impl<'a> ::std::io::Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+                let n = ::std::cmp::min(::std::cmp::min(self.size, buf.len()), self.bytes.len());
+                buf[..n].copy_from_slice(&self.bytes[..n]);
+                self.bytes = &self.bytes[n..];
+                Ok(n)
+            }
+        }
+
+        let input = "(définir (日本 x:y)\n  ; 注釈 comment\n  \"a\nmultiline \\\" string\"    [é]) \"unclosed";
+        let expected: Vec<_> = tokenize(input.into(), &[":"]).collect();
+        for size in 1..8 {
+            let reader = Trickle { bytes: input.as_bytes(), size: size };
+            let tokens: Vec<_> = tokenize_reader(reader, &[":"]).collect();
+            assert_eq!(tokens.len(), expected.len());
+            for (token, expected) in tokens.iter().zip(expected.iter()) {
+                match (token, expected) {
+                    (&Ok(ref a), &Ok(ref b)) => assert_eq!(a, b),
+                    (&Err(TokError::UnclosedString(ref a)), &Err(TokError::UnclosedString(ref b))) => {
+                        assert!(a.full_text.is_empty());
+                        assert_eq!((a.text_bytes, a.lines_covered, a.columns),
+                                   (b.text_bytes, b.lines_covered, b.columns));
+                    }
+                    other => panic!("tokens differ: {:?}", other),
+                }
+            }
+        }
+
+        // A token longer than one read
+        let long = format!("(a {} b)", "x".repeat(50000));
+        let expected: Vec<_> = tokenize(long.as_str().into(), &[]).collect();
+        assert_eq!(tokenize_reader(long.as_bytes(), &[]).collect::<Vec<_>>(), expected);
+
+        let invalid: &[u8] = b"(a \xff)";
+        let tokens: Vec<_> = tokenize_reader(invalid, &[]).collect();
+        assert_eq!(tokens.last(), Some(&Err(TokError::Io("stream did not contain valid UTF-8".into()))));
+    }
 }